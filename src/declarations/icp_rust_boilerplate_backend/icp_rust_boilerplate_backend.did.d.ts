@@ -1,48 +1,536 @@
 import type { Principal } from '@dfinity/principal';
 import type { ActorMethod } from '@dfinity/agent';
 
-export type Error = { 'NotFound' : { 'msg' : string } } |
-  { 'Unauthorized' : { 'msg' : string } };
+export type AdmissionMode = { 'Partial' : null } |
+  { 'AllOrNothing' : null } |
+  { 'Queue' : null };
+export interface AnchorageEntry {
+  'seq' : bigint,
+  'ship_id' : bigint,
+  'class' : PriorityClass,
+  'port_id' : bigint,
+  'queued_at' : bigint,
+}
+export interface ApprovalPolicy {
+  'ttl_seconds' : bigint,
+  'threshold' : number,
+  'operations' : Array<Operation>,
+  'approvers' : Array<Principal>,
+}
+export interface ArrivalReport {
+  'admitted' : BigUint64Array | bigint[],
+  'rejected' : BigUint64Array | bigint[],
+  'queued' : BigUint64Array | bigint[],
+}
+export interface AuditEntry {
+  'id' : bigint,
+  'method' : string,
+  'after' : [] | [string],
+  'args' : string,
+  'before' : [] | [string],
+  'timestamp' : bigint,
+  'entity_kind' : EntityKind,
+  'caller' : Principal,
+  'entity_id' : [] | [bigint],
+}
+export interface AuditFilter {
+  'to' : [] | [bigint],
+  'from' : [] | [bigint],
+  'entity_kind' : [] | [EntityKind],
+  'caller' : [] | [Principal],
+  'entity_id' : [] | [bigint],
+}
+export interface AuditPage {
+  'total' : bigint,
+  'entries' : Array<AuditEntry>,
+  'next_cursor' : [] | [bigint],
+}
+export interface Berth {
+  'id' : bigint,
+  'status' : BerthStatus,
+  'occupant' : [] | [BerthOccupant],
+  'name' : string,
+  'port_id' : bigint,
+  'length' : number,
+  'max_draft' : number,
+}
+export type BerthAssignment = { 'Ship' : bigint } |
+  { 'PortCall' : bigint };
+export interface BerthOccupant {
+  'ship_id' : bigint,
+  'port_call_id' : [] | [bigint],
+}
+export interface BerthPayload {
+  'name' : string,
+  'length' : number,
+  'max_draft' : number,
+}
+export interface BerthPlan {
+  'assignments' : Array<PlannedBerthing>,
+  'total_waiting' : bigint,
+  'port_id' : bigint,
+  'unassigned' : BigUint64Array | bigint[],
+  'all_orders_tried' : boolean,
+}
+export interface BerthReservation {
+  'id' : bigint,
+  'end' : bigint,
+  'ship_id' : bigint,
+  'status' : ReservationStatus,
+  'port_call_id' : [] | [bigint],
+  'created_by' : Principal,
+  'port_id' : bigint,
+  'start' : bigint,
+  'berth_id' : bigint,
+}
+export type BerthStatus = { 'Available' : null } |
+  { 'Maintenance' : null } |
+  { 'Occupied' : null };
+export interface CertifiedPort {
+  'certificate' : Uint8Array | number[],
+  'port' : Port,
+  'witness' : Uint8Array | number[],
+}
+export interface CertifiedPortPage {
+  'certificate' : Uint8Array | number[],
+  'witness' : Uint8Array | number[],
+  'next_cursor' : [] | [bigint],
+  'ports' : Array<Port>,
+}
+export interface Counts {
+  'ships_in_port' : bigint,
+  'total_capacity' : bigint,
+  'archived_ports' : bigint,
+  'ships' : bigint,
+  'users' : bigint,
+  'ports' : bigint,
+}
+export type EntityKind = { 'Anchorage' : null } |
+  { 'Port' : null } |
+  { 'Role' : null } |
+  { 'Ship' : null } |
+  { 'User' : null } |
+  { 'Berth' : null } |
+  { 'Reservation' : null } |
+  { 'Settings' : null } |
+  { 'PortCall' : null } |
+  { 'Proposal' : null } |
+  { 'Transfer' : null };
+export type Error = { 'Overflow' : { 'msg' : string } } |
+  {
+    'InvalidInput' : { 'msg' : string, 'violations' : Array<FieldViolation> }
+  } |
+  { 'CapacityExceeded' : { 'msg' : string } } |
+  { 'NotFound' : { 'msg' : string } } |
+  { 'Unauthorized' : { 'msg' : string } } |
+  { 'Conflict' : { 'msg' : string } };
+export interface ErrorCount { 'count' : bigint, 'variant' : string }
+export interface FieldViolation { 'field' : string, 'reason' : string }
+export interface FreeWindow {
+  'end' : bigint,
+  'start' : bigint,
+  'berth_id' : bigint,
+}
+export interface HttpRequest {
+  'url' : string,
+  'method' : string,
+  'body' : Uint8Array | number[],
+  'headers' : Array<[string, string]>,
+}
+export interface HttpResponse {
+  'body' : Uint8Array | number[],
+  'headers' : Array<[string, string]>,
+  'status_code' : number,
+}
+export type IdMode = { 'Sequential' : null } |
+  { 'Obscured' : null };
+export interface MemoryUsage { 'memory_id' : number, 'bytes' : bigint }
+export interface MethodMetrics {
+  'method' : string,
+  'calls' : bigint,
+  'errors' : Array<ErrorCount>,
+}
+export interface Metrics {
+  'memory' : Array<MemoryUsage>,
+  'stable_memory_bytes' : bigint,
+  'cycles' : bigint,
+  'timestamp' : bigint,
+  'update_methods' : Array<MethodMetrics>,
+  'counts' : Counts,
+  'heap_bytes' : bigint,
+}
+export interface MovementEvent {
+  'id' : bigint,
+  'ship_id' : bigint,
+  'kind' : MovementKind,
+  'port_id' : bigint,
+  'timestamp' : bigint,
+  'occupancy_after' : number,
+}
+export type MovementKind = { 'Arrival' : null } |
+  { 'Departure' : null };
+export type Operation = { 'TransferShips' : null } |
+  { 'DeletePort' : null } |
+  { 'ChangeCapacity' : null };
+export interface PlanRequest { 'port_call_id' : bigint, 'priority' : number }
+export interface PlannedBerthing {
+  'end' : bigint,
+  'ship_id' : bigint,
+  'port_call_id' : bigint,
+  'start' : bigint,
+  'waiting' : bigint,
+  'berth_id' : bigint,
+}
 export interface Port {
   'id' : bigint,
+  'updated_at' : bigint,
+  'updated_by' : [] | [Principal],
   'current_ships' : number,
   'name' : string,
+  'created_at' : bigint,
+  'version' : bigint,
   'capacity' : number,
   'location' : string,
+  'archived_at' : [] | [bigint],
+}
+export interface PortCall {
+  'id' : bigint,
+  'ata' : [] | [bigint],
+  'atd' : [] | [bigint],
+  'eta' : bigint,
+  'etd' : bigint,
+  'ship_id' : bigint,
+  'status' : PortCallStatus,
+  'created_by' : Principal,
+  'port_id' : bigint,
+}
+export interface PortCallPayload {
+  'eta' : bigint,
+  'etd' : bigint,
+  'ship_id' : bigint,
+  'port_id' : bigint,
+}
+export type PortCallStatus = { 'Announced' : null } |
+  { 'Berthed' : null } |
+  { 'Arrived' : null } |
+  { 'Approved' : null } |
+  { 'Departed' : null } |
+  { 'Cancelled' : null };
+export interface PortPage {
+  'total' : bigint,
+  'next_cursor' : [] | [Uint8Array | number[]],
+  'ports' : Array<Port>,
+}
+export interface PortPatch {
+  'name' : [] | [string],
+  'capacity' : [] | [number],
+  'location' : [] | [string],
 }
 export interface PortPayload {
   'name' : string,
   'capacity' : number,
   'location' : string,
 }
-export type Result = { 'Ok' : null } |
+export interface PortSearch {
+  'min_free_capacity' : [] | [number],
+  'name_prefix' : [] | [string],
+  'location' : [] | [string],
+}
+export type PortSort = { 'Id' : null } |
+  { 'FreeCapacity' : null } |
+  { 'Name' : null } |
+  { 'Location' : null } |
+  { 'Occupancy' : null };
+export type PriorityClass = { 'Liner' : null } |
+  { 'Tramp' : null } |
+  { 'Passenger' : null } |
+  { 'Emergency' : null };
+export interface Proposal {
+  'id' : bigint,
+  'status' : ProposalStatus,
+  'action' : ProposalAction,
+  'failure' : [] | [Error],
+  'executed_at' : [] | [bigint],
+  'threshold' : number,
+  'created_at' : bigint,
+  'rejections' : Array<Principal>,
+  'proposer' : Principal,
+  'expires_at' : bigint,
+  'approvals' : Array<Principal>,
+  'approvers' : Array<Principal>,
+}
+export type ProposalAction = { 'TransferShips' : TransferRequest } |
+  { 'DeletePort' : { 'port_id' : bigint } } |
+  { 'ChangeCapacity' : { 'port_id' : bigint, 'capacity' : number } };
+export interface ProposalPage {
+  'total' : bigint,
+  'next_cursor' : [] | [bigint],
+  'proposals' : Array<Proposal>,
+}
+export type ProposalStatus = { 'Failed' : null } |
+  { 'Open' : null } |
+  { 'Rejected' : null } |
+  { 'Executed' : null } |
+  { 'Expired' : null };
+export interface QueuePosition {
+  'entry' : AnchorageEntry,
+  'position' : number,
+  'estimated_wait' : bigint,
+}
+export interface ReservationPayload {
+  'end' : bigint,
+  'ship_id' : bigint,
+  'port_call_id' : [] | [bigint],
+  'port_id' : bigint,
+  'start' : bigint,
+  'berth_id' : bigint,
+}
+export type ReservationStatus = { 'Active' : null } |
+  { 'Cancelled' : null };
+export type Result = { 'Ok' : Berth } |
   { 'Err' : Error };
 export type Result_1 = { 'Ok' : Port } |
   { 'Err' : Error };
-export type Result_2 = { 'Ok' : User } |
+export type Result_10 = { 'Ok' : Array<RoleAssignment> } |
+  { 'Err' : Error };
+export type Result_11 = { 'Ok' : AuditPage } |
+  { 'Err' : Error };
+export type Result_12 = { 'Ok' : CertifiedPort } |
+  { 'Err' : Error };
+export type Result_13 = { 'Ok' : Metrics } |
+  { 'Err' : Error };
+export type Result_14 = { 'Ok' : Array<MovementEvent> } |
   { 'Err' : Error };
+export type Result_15 = { 'Ok' : number } |
+  { 'Err' : Error };
+export type Result_16 = { 'Ok' : Array<Ship> } |
+  { 'Err' : Error };
+export type Result_17 = { 'Ok' : Array<Transfer> } |
+  { 'Err' : Error };
+export type Result_18 = { 'Ok' : QueuePosition } |
+  { 'Err' : Error };
+export type Result_19 = { 'Ok' : Transfer } |
+  { 'Err' : Error };
+export type Result_2 = { 'Ok' : Ship } |
+  { 'Err' : Error };
+export type Result_20 = { 'Ok' : null } |
+  { 'Err' : Error };
+export type Result_21 = { 'Ok' : CertifiedPortPage } |
+  { 'Err' : Error };
+export type Result_22 = { 'Ok' : PortPage } |
+  { 'Err' : Error };
+export type Result_23 = { 'Ok' : ProposalPage } |
+  { 'Err' : Error };
+export type Result_24 = { 'Ok' : BerthPlan } |
+  { 'Err' : Error };
+export type Result_25 = { 'Ok' : Role } |
+  { 'Err' : Error };
+export type Result_26 = { 'Ok' : ApprovalPolicy } |
+  { 'Err' : Error };
+export type Result_27 = { 'Ok' : IdMode } |
+  { 'Err' : Error };
+export type Result_28 = { 'Ok' : ArrivalReport } |
+  { 'Err' : Error };
+export type Result_29 = { 'Ok' : BigUint64Array | bigint[] } |
+  { 'Err' : Error };
+export type Result_3 = { 'Ok' : User } |
+  { 'Err' : Error };
+export type Result_4 = { 'Ok' : PortCall } |
+  { 'Err' : Error };
+export type Result_5 = { 'Ok' : Array<BerthReservation> } |
+  { 'Err' : Error };
+export type Result_6 = { 'Ok' : Proposal } |
+  { 'Err' : Error };
+export type Result_7 = { 'Ok' : BerthReservation } |
+  { 'Err' : Error };
+export type Result_8 = { 'Ok' : AnchorageEntry } |
+  { 'Err' : Error };
+export type Result_9 = { 'Ok' : Array<FreeWindow> } |
+  { 'Err' : Error };
+export type Role = { 'Viewer' : null } |
+  { 'Operator' : null } |
+  { 'PortAuthority' : null } |
+  { 'Admin' : null };
+export interface RoleAssignment { 'principal' : Principal, 'role' : Role }
+export interface Ship {
+  'id' : bigint,
+  'loa' : number,
+  'beam' : number,
+  'flag' : string,
+  'name' : string,
+  'gross_tonnage' : number,
+  'imo_number' : string,
+  'current_port_id' : [] | [bigint],
+  'ship_type' : ShipType,
+  'draft' : number,
+}
+export interface ShipPayload {
+  'loa' : number,
+  'beam' : number,
+  'flag' : string,
+  'name' : string,
+  'gross_tonnage' : number,
+  'imo_number' : string,
+  'ship_type' : ShipType,
+  'draft' : number,
+}
+export type ShipType = { 'GeneralCargo' : null } |
+  { 'Bulk' : null } |
+  { 'RoRo' : null } |
+  { 'Container' : null } |
+  { 'Passenger' : null } |
+  { 'Tanker' : null } |
+  { 'Other' : null };
+export interface Transfer {
+  'id' : bigint,
+  'status' : TransferStatus,
+  'ship_ids' : BigUint64Array | bigint[],
+  'approved_by' : [] | [Principal],
+  'requested_by' : Principal,
+  'source_port_id' : bigint,
+  'selection' : TransferSelection,
+  'timestamp' : bigint,
+  'destination_port_id' : bigint,
+  'idempotency_key' : [] | [string],
+}
+export interface TransferRequest {
+  'source_port_id' : bigint,
+  'selection' : TransferSelection,
+  'destination_port_id' : bigint,
+  'idempotency_key' : [] | [string],
+}
+export type TransferSelection = { 'Ships' : BigUint64Array | bigint[] } |
+  { 'Count' : number };
+export type TransferStatus = { 'Completed' : null };
 export interface User {
+  'updated_at' : bigint,
+  'updated_by' : [] | [Principal],
   'username' : string,
+  'owner' : [] | [Principal],
+  'created_at' : bigint,
   'user_id' : bigint,
   'email' : string,
+  'version' : bigint,
+  'archived_at' : [] | [bigint],
+}
+export interface UserPage {
+  'total' : bigint,
+  'users' : Array<User>,
+  'next_cursor' : [] | [bigint],
+}
+export interface UserPatch {
+  'username' : [] | [string],
+  'email' : [] | [string],
 }
 export interface UserPayload { 'username' : string, 'email' : string }
 export interface _SERVICE {
-  'add_port' : ActorMethod<[PortPayload], [] | [Port]>,
-  'add_ship_to_port' : ActorMethod<[bigint], Result>,
-  'add_user' : ActorMethod<[UserPayload], [] | [User]>,
+  'add_berth' : ActorMethod<[bigint, BerthPayload], Result>,
+  'add_port' : ActorMethod<[PortPayload], Result_1>,
+  'add_ship' : ActorMethod<[ShipPayload], Result_2>,
+  'add_ship_to_port' : ActorMethod<[bigint, bigint], Result_2>,
+  'add_user' : ActorMethod<[UserPayload, [] | [Principal]], Result_3>,
+  'allocate_berth' : ActorMethod<[bigint, bigint, BerthAssignment], Result>,
+  'announce_port_call' : ActorMethod<[PortCallPayload], Result_4>,
+  'apply_plan' : ActorMethod<[BerthPlan], Result_5>,
+  'approve_port_call' : ActorMethod<[bigint], Result_4>,
+  'approve_proposal' : ActorMethod<[bigint], Result_6>,
+  'archive_port' : ActorMethod<[bigint], Result_1>,
+  'archive_user' : ActorMethod<[bigint], Result_3>,
+  'cancel_port_call' : ActorMethod<[bigint], Result_4>,
+  'cancel_reservation' : ActorMethod<[bigint], Result_7>,
+  'create_proposal' : ActorMethod<[ProposalAction], Result_6>,
+  'delete_berth' : ActorMethod<[bigint, bigint], Result>,
+  'delete_me' : ActorMethod<[], Result_3>,
   'delete_port' : ActorMethod<[bigint], Result_1>,
-  'delete_user' : ActorMethod<[bigint], Result_2>,
-  'get_admin' : ActorMethod<[], bigint>,
+  'delete_ship' : ActorMethod<[bigint], Result_2>,
+  'delete_user' : ActorMethod<[bigint], Result_3>,
+  'enqueue_ship' : ActorMethod<[bigint, bigint, PriorityClass], Result_8>,
+  'find_free_berth_windows' : ActorMethod<
+    [bigint, number, number, bigint, bigint, bigint],
+    Result_9
+  >,
   'get_all_ports' : ActorMethod<[], Array<Port>>,
+  'get_all_roles' : ActorMethod<[], Result_10>,
+  'get_all_ships' : ActorMethod<[], Array<Ship>>,
   'get_all_users' : ActorMethod<[], Array<User>>,
+  'get_anchorage_queue' : ActorMethod<[bigint], Array<AnchorageEntry>>,
+  'get_approval_policy' : ActorMethod<[], ApprovalPolicy>,
+  'get_audit_log' : ActorMethod<
+    [AuditFilter, [] | [bigint], bigint],
+    Result_11
+  >,
+  'get_berth' : ActorMethod<[bigint, bigint], Result>,
+  'get_berth_reservations' : ActorMethod<
+    [bigint, bigint, bigint],
+    Array<BerthReservation>
+  >,
+  'get_certified_port' : ActorMethod<[bigint], Result_12>,
+  'get_id_mode' : ActorMethod<[], IdMode>,
+  'get_metrics' : ActorMethod<[], Result_13>,
+  'get_my_role' : ActorMethod<[], [] | [Role]>,
   'get_port' : ActorMethod<[bigint], Result_1>,
-  'get_user' : ActorMethod<[bigint], Result_2>,
-  'ships_arrival' : ActorMethod<[bigint, number], Result>,
-  'transfer_ships_admin' : ActorMethod<
-    [bigint, bigint, number, bigint],
-    Result
-  >,
-  'update_port' : ActorMethod<[bigint, PortPayload], Result_1>,
-  'update_user' : ActorMethod<[bigint, UserPayload], Result_2>,
+  'get_port_berths' : ActorMethod<[bigint], Array<Berth>>,
+  'get_port_call' : ActorMethod<[bigint], Result_4>,
+  'get_port_calls' : ActorMethod<[bigint], Array<PortCall>>,
+  'get_port_movements' : ActorMethod<
+    [bigint, [] | [bigint], [] | [bigint]],
+    Result_14
+  >,
+  'get_port_occupancy_at' : ActorMethod<[bigint, bigint], Result_15>,
+  'get_port_ships' : ActorMethod<[bigint], Result_16>,
+  'get_port_transfers' : ActorMethod<[bigint], Result_17>,
+  'get_proposal' : ActorMethod<[bigint], Result_6>,
+  'get_queue_position' : ActorMethod<[bigint, bigint], Result_18>,
+  'get_reservation' : ActorMethod<[bigint], Result_7>,
+  'get_ship' : ActorMethod<[bigint], Result_2>,
+  'get_transfer' : ActorMethod<[bigint], Result_19>,
+  'get_user' : ActorMethod<[bigint], Result_3>,
+  'grant_role' : ActorMethod<[Principal, Role], Result_20>,
+  'http_request' : ActorMethod<[HttpRequest], HttpResponse>,
+  'leave_anchorage' : ActorMethod<[bigint, bigint], Result_8>,
+  'list_certified_ports' : ActorMethod<[[] | [bigint], bigint], Result_21>,
+  'list_ports' : ActorMethod<
+    [[] | [Uint8Array | number[]], bigint, [] | [PortSort]],
+    Result_22
+  >,
+  'list_proposals' : ActorMethod<
+    [[] | [ProposalStatus], [] | [bigint], bigint],
+    Result_23
+  >,
+  'list_users' : ActorMethod<[[] | [bigint], bigint], UserPage>,
+  'plan_berths' : ActorMethod<[bigint, Array<PlanRequest>], Result_24>,
+  'record_port_call_arrival' : ActorMethod<[bigint], Result_4>,
+  'record_port_call_berthing' : ActorMethod<[bigint, bigint], Result_4>,
+  'record_port_call_departure' : ActorMethod<[bigint], Result_4>,
+  'register_me' : ActorMethod<[UserPayload], Result_3>,
+  'reject_proposal' : ActorMethod<[bigint], Result_6>,
+  'release_berth' : ActorMethod<[bigint, bigint], Result>,
+  'reschedule_reservation' : ActorMethod<[bigint, bigint, bigint], Result_7>,
+  'reserve_berth' : ActorMethod<[ReservationPayload], Result_7>,
+  'restore_port' : ActorMethod<[bigint], Result_1>,
+  'restore_user' : ActorMethod<[bigint], Result_3>,
+  'revoke_role' : ActorMethod<[Principal], Result_25>,
+  'search_ports' : ActorMethod<
+    [PortSearch, [] | [Uint8Array | number[]], bigint],
+    Result_22
+  >,
+  'set_approval_policy' : ActorMethod<[ApprovalPolicy], Result_26>,
+  'set_berth_maintenance' : ActorMethod<[bigint, bigint, boolean], Result>,
+  'set_id_mode' : ActorMethod<[IdMode], Result_27>,
+  'ship_departure' : ActorMethod<[bigint, bigint], Result_2>,
+  'ships_arrival' : ActorMethod<
+    [bigint, BigUint64Array | bigint[], [] | [AdmissionMode]],
+    Result_28
+  >,
+  'ships_departure' : ActorMethod<
+    [bigint, BigUint64Array | bigint[]],
+    Result_29
+  >,
+  'transfer_ships_admin' : ActorMethod<[TransferRequest], Result_19>,
+  'update_berth' : ActorMethod<[bigint, bigint, BerthPayload], Result>,
+  'update_my_profile' : ActorMethod<[UserPatch, bigint], Result_3>,
+  'update_port' : ActorMethod<[bigint, PortPatch, bigint], Result_1>,
+  'update_ship' : ActorMethod<[bigint, ShipPayload], Result_2>,
+  'update_user' : ActorMethod<[bigint, UserPatch, bigint], Result_3>,
+  'whoami' : ActorMethod<[], Result_3>,
 }
@@ -1,4 +1,43 @@
 export const idlFactory = ({ IDL }) => {
+  const BerthPayload = IDL.Record({
+    'name' : IDL.Text,
+    'length' : IDL.Float64,
+    'max_draft' : IDL.Float64,
+  });
+  const BerthStatus = IDL.Variant({
+    'Available' : IDL.Null,
+    'Maintenance' : IDL.Null,
+    'Occupied' : IDL.Null,
+  });
+  const BerthOccupant = IDL.Record({
+    'ship_id' : IDL.Nat64,
+    'port_call_id' : IDL.Opt(IDL.Nat64),
+  });
+  const Berth = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : BerthStatus,
+    'occupant' : IDL.Opt(BerthOccupant),
+    'name' : IDL.Text,
+    'port_id' : IDL.Nat64,
+    'length' : IDL.Float64,
+    'max_draft' : IDL.Float64,
+  });
+  const FieldViolation = IDL.Record({
+    'field' : IDL.Text,
+    'reason' : IDL.Text,
+  });
+  const Error = IDL.Variant({
+    'Overflow' : IDL.Record({ 'msg' : IDL.Text }),
+    'InvalidInput' : IDL.Record({
+      'msg' : IDL.Text,
+      'violations' : IDL.Vec(FieldViolation),
+    }),
+    'CapacityExceeded' : IDL.Record({ 'msg' : IDL.Text }),
+    'NotFound' : IDL.Record({ 'msg' : IDL.Text }),
+    'Unauthorized' : IDL.Record({ 'msg' : IDL.Text }),
+    'Conflict' : IDL.Record({ 'msg' : IDL.Text }),
+  });
+  const Result = IDL.Variant({ 'Ok' : Berth, 'Err' : Error });
   const PortPayload = IDL.Record({
     'name' : IDL.Text,
     'capacity' : IDL.Nat32,
@@ -6,43 +45,582 @@ export const idlFactory = ({ IDL }) => {
   });
   const Port = IDL.Record({
     'id' : IDL.Nat64,
+    'updated_at' : IDL.Nat64,
+    'updated_by' : IDL.Opt(IDL.Principal),
     'current_ships' : IDL.Nat32,
     'name' : IDL.Text,
+    'created_at' : IDL.Nat64,
+    'version' : IDL.Nat64,
     'capacity' : IDL.Nat32,
     'location' : IDL.Text,
+    'archived_at' : IDL.Opt(IDL.Nat64),
   });
-  const Error = IDL.Variant({
-    'NotFound' : IDL.Record({ 'msg' : IDL.Text }),
-    'Unauthorized' : IDL.Record({ 'msg' : IDL.Text }),
+  const Result_1 = IDL.Variant({ 'Ok' : Port, 'Err' : Error });
+  const ShipType = IDL.Variant({
+    'GeneralCargo' : IDL.Null,
+    'Bulk' : IDL.Null,
+    'RoRo' : IDL.Null,
+    'Container' : IDL.Null,
+    'Passenger' : IDL.Null,
+    'Tanker' : IDL.Null,
+    'Other' : IDL.Null,
+  });
+  const ShipPayload = IDL.Record({
+    'loa' : IDL.Float64,
+    'beam' : IDL.Float64,
+    'flag' : IDL.Text,
+    'name' : IDL.Text,
+    'gross_tonnage' : IDL.Nat32,
+    'imo_number' : IDL.Text,
+    'ship_type' : ShipType,
+    'draft' : IDL.Float64,
+  });
+  const Ship = IDL.Record({
+    'id' : IDL.Nat64,
+    'loa' : IDL.Float64,
+    'beam' : IDL.Float64,
+    'flag' : IDL.Text,
+    'name' : IDL.Text,
+    'gross_tonnage' : IDL.Nat32,
+    'imo_number' : IDL.Text,
+    'current_port_id' : IDL.Opt(IDL.Nat64),
+    'ship_type' : ShipType,
+    'draft' : IDL.Float64,
   });
-  const Result = IDL.Variant({ 'Ok' : IDL.Null, 'Err' : Error });
+  const Result_2 = IDL.Variant({ 'Ok' : Ship, 'Err' : Error });
   const UserPayload = IDL.Record({ 'username' : IDL.Text, 'email' : IDL.Text });
   const User = IDL.Record({
+    'updated_at' : IDL.Nat64,
+    'updated_by' : IDL.Opt(IDL.Principal),
     'username' : IDL.Text,
+    'owner' : IDL.Opt(IDL.Principal),
+    'created_at' : IDL.Nat64,
     'user_id' : IDL.Nat64,
     'email' : IDL.Text,
+    'version' : IDL.Nat64,
+    'archived_at' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_3 = IDL.Variant({ 'Ok' : User, 'Err' : Error });
+  const BerthAssignment = IDL.Variant({
+    'Ship' : IDL.Nat64,
+    'PortCall' : IDL.Nat64,
+  });
+  const PortCallPayload = IDL.Record({
+    'eta' : IDL.Nat64,
+    'etd' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'port_id' : IDL.Nat64,
+  });
+  const PortCallStatus = IDL.Variant({
+    'Announced' : IDL.Null,
+    'Berthed' : IDL.Null,
+    'Arrived' : IDL.Null,
+    'Approved' : IDL.Null,
+    'Departed' : IDL.Null,
+    'Cancelled' : IDL.Null,
+  });
+  const PortCall = IDL.Record({
+    'id' : IDL.Nat64,
+    'ata' : IDL.Opt(IDL.Nat64),
+    'atd' : IDL.Opt(IDL.Nat64),
+    'eta' : IDL.Nat64,
+    'etd' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'status' : PortCallStatus,
+    'created_by' : IDL.Principal,
+    'port_id' : IDL.Nat64,
+  });
+  const Result_4 = IDL.Variant({ 'Ok' : PortCall, 'Err' : Error });
+  const PlannedBerthing = IDL.Record({
+    'end' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'port_call_id' : IDL.Nat64,
+    'start' : IDL.Nat64,
+    'waiting' : IDL.Nat64,
+    'berth_id' : IDL.Nat64,
+  });
+  const BerthPlan = IDL.Record({
+    'assignments' : IDL.Vec(PlannedBerthing),
+    'total_waiting' : IDL.Nat64,
+    'port_id' : IDL.Nat64,
+    'unassigned' : IDL.Vec(IDL.Nat64),
+    'all_orders_tried' : IDL.Bool,
+  });
+  const ReservationStatus = IDL.Variant({
+    'Active' : IDL.Null,
+    'Cancelled' : IDL.Null,
+  });
+  const BerthReservation = IDL.Record({
+    'id' : IDL.Nat64,
+    'end' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'status' : ReservationStatus,
+    'port_call_id' : IDL.Opt(IDL.Nat64),
+    'created_by' : IDL.Principal,
+    'port_id' : IDL.Nat64,
+    'start' : IDL.Nat64,
+    'berth_id' : IDL.Nat64,
+  });
+  const Result_5 = IDL.Variant({
+    'Ok' : IDL.Vec(BerthReservation),
+    'Err' : Error,
+  });
+  const ProposalStatus = IDL.Variant({
+    'Failed' : IDL.Null,
+    'Open' : IDL.Null,
+    'Rejected' : IDL.Null,
+    'Executed' : IDL.Null,
+    'Expired' : IDL.Null,
+  });
+  const TransferSelection = IDL.Variant({
+    'Ships' : IDL.Vec(IDL.Nat64),
+    'Count' : IDL.Nat32,
+  });
+  const TransferRequest = IDL.Record({
+    'source_port_id' : IDL.Nat64,
+    'selection' : TransferSelection,
+    'destination_port_id' : IDL.Nat64,
+    'idempotency_key' : IDL.Opt(IDL.Text),
+  });
+  const ProposalAction = IDL.Variant({
+    'TransferShips' : TransferRequest,
+    'DeletePort' : IDL.Record({ 'port_id' : IDL.Nat64 }),
+    'ChangeCapacity' : IDL.Record({
+      'port_id' : IDL.Nat64,
+      'capacity' : IDL.Nat32,
+    }),
+  });
+  const Proposal = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : ProposalStatus,
+    'action' : ProposalAction,
+    'failure' : IDL.Opt(Error),
+    'executed_at' : IDL.Opt(IDL.Nat64),
+    'threshold' : IDL.Nat32,
+    'created_at' : IDL.Nat64,
+    'rejections' : IDL.Vec(IDL.Principal),
+    'proposer' : IDL.Principal,
+    'expires_at' : IDL.Nat64,
+    'approvals' : IDL.Vec(IDL.Principal),
+    'approvers' : IDL.Vec(IDL.Principal),
+  });
+  const Result_6 = IDL.Variant({ 'Ok' : Proposal, 'Err' : Error });
+  const Result_7 = IDL.Variant({ 'Ok' : BerthReservation, 'Err' : Error });
+  const PriorityClass = IDL.Variant({
+    'Liner' : IDL.Null,
+    'Tramp' : IDL.Null,
+    'Passenger' : IDL.Null,
+    'Emergency' : IDL.Null,
+  });
+  const AnchorageEntry = IDL.Record({
+    'seq' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'class' : PriorityClass,
+    'port_id' : IDL.Nat64,
+    'queued_at' : IDL.Nat64,
+  });
+  const Result_8 = IDL.Variant({ 'Ok' : AnchorageEntry, 'Err' : Error });
+  const FreeWindow = IDL.Record({
+    'end' : IDL.Nat64,
+    'start' : IDL.Nat64,
+    'berth_id' : IDL.Nat64,
+  });
+  const Result_9 = IDL.Variant({ 'Ok' : IDL.Vec(FreeWindow), 'Err' : Error });
+  const Role = IDL.Variant({
+    'Viewer' : IDL.Null,
+    'Operator' : IDL.Null,
+    'PortAuthority' : IDL.Null,
+    'Admin' : IDL.Null,
+  });
+  const RoleAssignment = IDL.Record({
+    'principal' : IDL.Principal,
+    'role' : Role,
+  });
+  const Result_10 = IDL.Variant({
+    'Ok' : IDL.Vec(RoleAssignment),
+    'Err' : Error,
+  });
+  const Operation = IDL.Variant({
+    'TransferShips' : IDL.Null,
+    'DeletePort' : IDL.Null,
+    'ChangeCapacity' : IDL.Null,
+  });
+  const ApprovalPolicy = IDL.Record({
+    'ttl_seconds' : IDL.Nat64,
+    'threshold' : IDL.Nat32,
+    'operations' : IDL.Vec(Operation),
+    'approvers' : IDL.Vec(IDL.Principal),
+  });
+  const EntityKind = IDL.Variant({
+    'Anchorage' : IDL.Null,
+    'Port' : IDL.Null,
+    'Role' : IDL.Null,
+    'Ship' : IDL.Null,
+    'User' : IDL.Null,
+    'Berth' : IDL.Null,
+    'Reservation' : IDL.Null,
+    'Settings' : IDL.Null,
+    'PortCall' : IDL.Null,
+    'Proposal' : IDL.Null,
+    'Transfer' : IDL.Null,
+  });
+  const AuditFilter = IDL.Record({
+    'to' : IDL.Opt(IDL.Nat64),
+    'from' : IDL.Opt(IDL.Nat64),
+    'entity_kind' : IDL.Opt(EntityKind),
+    'caller' : IDL.Opt(IDL.Principal),
+    'entity_id' : IDL.Opt(IDL.Nat64),
+  });
+  const AuditEntry = IDL.Record({
+    'id' : IDL.Nat64,
+    'method' : IDL.Text,
+    'after' : IDL.Opt(IDL.Text),
+    'args' : IDL.Text,
+    'before' : IDL.Opt(IDL.Text),
+    'timestamp' : IDL.Nat64,
+    'entity_kind' : EntityKind,
+    'caller' : IDL.Principal,
+    'entity_id' : IDL.Opt(IDL.Nat64),
+  });
+  const AuditPage = IDL.Record({
+    'total' : IDL.Nat64,
+    'entries' : IDL.Vec(AuditEntry),
+    'next_cursor' : IDL.Opt(IDL.Nat64),
+  });
+  const Result_11 = IDL.Variant({ 'Ok' : AuditPage, 'Err' : Error });
+  const CertifiedPort = IDL.Record({
+    'certificate' : IDL.Vec(IDL.Nat8),
+    'port' : Port,
+    'witness' : IDL.Vec(IDL.Nat8),
+  });
+  const Result_12 = IDL.Variant({ 'Ok' : CertifiedPort, 'Err' : Error });
+  const IdMode = IDL.Variant({
+    'Sequential' : IDL.Null,
+    'Obscured' : IDL.Null,
+  });
+  const MemoryUsage = IDL.Record({
+    'memory_id' : IDL.Nat8,
+    'bytes' : IDL.Nat64,
+  });
+  const ErrorCount = IDL.Record({ 'count' : IDL.Nat64, 'variant' : IDL.Text });
+  const MethodMetrics = IDL.Record({
+    'method' : IDL.Text,
+    'calls' : IDL.Nat64,
+    'errors' : IDL.Vec(ErrorCount),
+  });
+  const Counts = IDL.Record({
+    'ships_in_port' : IDL.Nat64,
+    'total_capacity' : IDL.Nat64,
+    'archived_ports' : IDL.Nat64,
+    'ships' : IDL.Nat64,
+    'users' : IDL.Nat64,
+    'ports' : IDL.Nat64,
+  });
+  const Metrics = IDL.Record({
+    'memory' : IDL.Vec(MemoryUsage),
+    'stable_memory_bytes' : IDL.Nat64,
+    'cycles' : IDL.Nat,
+    'timestamp' : IDL.Nat64,
+    'update_methods' : IDL.Vec(MethodMetrics),
+    'counts' : Counts,
+    'heap_bytes' : IDL.Nat64,
+  });
+  const Result_13 = IDL.Variant({ 'Ok' : Metrics, 'Err' : Error });
+  const MovementKind = IDL.Variant({
+    'Arrival' : IDL.Null,
+    'Departure' : IDL.Null,
+  });
+  const MovementEvent = IDL.Record({
+    'id' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'kind' : MovementKind,
+    'port_id' : IDL.Nat64,
+    'timestamp' : IDL.Nat64,
+    'occupancy_after' : IDL.Nat32,
+  });
+  const Result_14 = IDL.Variant({
+    'Ok' : IDL.Vec(MovementEvent),
+    'Err' : Error,
+  });
+  const Result_15 = IDL.Variant({ 'Ok' : IDL.Nat32, 'Err' : Error });
+  const Result_16 = IDL.Variant({ 'Ok' : IDL.Vec(Ship), 'Err' : Error });
+  const TransferStatus = IDL.Variant({ 'Completed' : IDL.Null });
+  const Transfer = IDL.Record({
+    'id' : IDL.Nat64,
+    'status' : TransferStatus,
+    'ship_ids' : IDL.Vec(IDL.Nat64),
+    'approved_by' : IDL.Opt(IDL.Principal),
+    'requested_by' : IDL.Principal,
+    'source_port_id' : IDL.Nat64,
+    'selection' : TransferSelection,
+    'timestamp' : IDL.Nat64,
+    'destination_port_id' : IDL.Nat64,
+    'idempotency_key' : IDL.Opt(IDL.Text),
+  });
+  const Result_17 = IDL.Variant({ 'Ok' : IDL.Vec(Transfer), 'Err' : Error });
+  const QueuePosition = IDL.Record({
+    'entry' : AnchorageEntry,
+    'position' : IDL.Nat32,
+    'estimated_wait' : IDL.Nat64,
+  });
+  const Result_18 = IDL.Variant({ 'Ok' : QueuePosition, 'Err' : Error });
+  const Result_19 = IDL.Variant({ 'Ok' : Transfer, 'Err' : Error });
+  const Result_20 = IDL.Variant({ 'Ok' : IDL.Null, 'Err' : Error });
+  const HttpRequest = IDL.Record({
+    'url' : IDL.Text,
+    'method' : IDL.Text,
+    'body' : IDL.Vec(IDL.Nat8),
+    'headers' : IDL.Vec(IDL.Tuple(IDL.Text, IDL.Text)),
+  });
+  const HttpResponse = IDL.Record({
+    'body' : IDL.Vec(IDL.Nat8),
+    'headers' : IDL.Vec(IDL.Tuple(IDL.Text, IDL.Text)),
+    'status_code' : IDL.Nat16,
+  });
+  const CertifiedPortPage = IDL.Record({
+    'certificate' : IDL.Vec(IDL.Nat8),
+    'witness' : IDL.Vec(IDL.Nat8),
+    'next_cursor' : IDL.Opt(IDL.Nat64),
+    'ports' : IDL.Vec(Port),
+  });
+  const Result_21 = IDL.Variant({ 'Ok' : CertifiedPortPage, 'Err' : Error });
+  const PortSort = IDL.Variant({
+    'Id' : IDL.Null,
+    'FreeCapacity' : IDL.Null,
+    'Name' : IDL.Null,
+    'Location' : IDL.Null,
+    'Occupancy' : IDL.Null,
+  });
+  const PortPage = IDL.Record({
+    'total' : IDL.Nat64,
+    'next_cursor' : IDL.Opt(IDL.Vec(IDL.Nat8)),
+    'ports' : IDL.Vec(Port),
+  });
+  const Result_22 = IDL.Variant({ 'Ok' : PortPage, 'Err' : Error });
+  const ProposalPage = IDL.Record({
+    'total' : IDL.Nat64,
+    'next_cursor' : IDL.Opt(IDL.Nat64),
+    'proposals' : IDL.Vec(Proposal),
+  });
+  const Result_23 = IDL.Variant({ 'Ok' : ProposalPage, 'Err' : Error });
+  const UserPage = IDL.Record({
+    'total' : IDL.Nat64,
+    'users' : IDL.Vec(User),
+    'next_cursor' : IDL.Opt(IDL.Nat64),
+  });
+  const PlanRequest = IDL.Record({
+    'port_call_id' : IDL.Nat64,
+    'priority' : IDL.Nat32,
+  });
+  const Result_24 = IDL.Variant({ 'Ok' : BerthPlan, 'Err' : Error });
+  const ReservationPayload = IDL.Record({
+    'end' : IDL.Nat64,
+    'ship_id' : IDL.Nat64,
+    'port_call_id' : IDL.Opt(IDL.Nat64),
+    'port_id' : IDL.Nat64,
+    'start' : IDL.Nat64,
+    'berth_id' : IDL.Nat64,
+  });
+  const Result_25 = IDL.Variant({ 'Ok' : Role, 'Err' : Error });
+  const PortSearch = IDL.Record({
+    'min_free_capacity' : IDL.Opt(IDL.Nat32),
+    'name_prefix' : IDL.Opt(IDL.Text),
+    'location' : IDL.Opt(IDL.Text),
+  });
+  const Result_26 = IDL.Variant({ 'Ok' : ApprovalPolicy, 'Err' : Error });
+  const Result_27 = IDL.Variant({ 'Ok' : IdMode, 'Err' : Error });
+  const AdmissionMode = IDL.Variant({
+    'Partial' : IDL.Null,
+    'AllOrNothing' : IDL.Null,
+    'Queue' : IDL.Null,
+  });
+  const ArrivalReport = IDL.Record({
+    'admitted' : IDL.Vec(IDL.Nat64),
+    'rejected' : IDL.Vec(IDL.Nat64),
+    'queued' : IDL.Vec(IDL.Nat64),
+  });
+  const Result_28 = IDL.Variant({ 'Ok' : ArrivalReport, 'Err' : Error });
+  const Result_29 = IDL.Variant({ 'Ok' : IDL.Vec(IDL.Nat64), 'Err' : Error });
+  const UserPatch = IDL.Record({
+    'username' : IDL.Opt(IDL.Text),
+    'email' : IDL.Opt(IDL.Text),
+  });
+  const PortPatch = IDL.Record({
+    'name' : IDL.Opt(IDL.Text),
+    'capacity' : IDL.Opt(IDL.Nat32),
+    'location' : IDL.Opt(IDL.Text),
   });
-  const Result_1 = IDL.Variant({ 'Ok' : Port, 'Err' : Error });
-  const Result_2 = IDL.Variant({ 'Ok' : User, 'Err' : Error });
   return IDL.Service({
-    'add_port' : IDL.Func([PortPayload], [IDL.Opt(Port)], []),
-    'add_ship_to_port' : IDL.Func([IDL.Nat64], [Result], []),
-    'add_user' : IDL.Func([UserPayload], [IDL.Opt(User)], []),
+    'add_berth' : IDL.Func([IDL.Nat64, BerthPayload], [Result], []),
+    'add_port' : IDL.Func([PortPayload], [Result_1], []),
+    'add_ship' : IDL.Func([ShipPayload], [Result_2], []),
+    'add_ship_to_port' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result_2], []),
+    'add_user' : IDL.Func(
+        [UserPayload, IDL.Opt(IDL.Principal)],
+        [Result_3],
+        [],
+      ),
+    'allocate_berth' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, BerthAssignment],
+        [Result],
+        [],
+      ),
+    'announce_port_call' : IDL.Func([PortCallPayload], [Result_4], []),
+    'apply_plan' : IDL.Func([BerthPlan], [Result_5], []),
+    'approve_port_call' : IDL.Func([IDL.Nat64], [Result_4], []),
+    'approve_proposal' : IDL.Func([IDL.Nat64], [Result_6], []),
+    'archive_port' : IDL.Func([IDL.Nat64], [Result_1], []),
+    'archive_user' : IDL.Func([IDL.Nat64], [Result_3], []),
+    'cancel_port_call' : IDL.Func([IDL.Nat64], [Result_4], []),
+    'cancel_reservation' : IDL.Func([IDL.Nat64], [Result_7], []),
+    'create_proposal' : IDL.Func([ProposalAction], [Result_6], []),
+    'delete_berth' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result], []),
+    'delete_me' : IDL.Func([], [Result_3], []),
     'delete_port' : IDL.Func([IDL.Nat64], [Result_1], []),
-    'delete_user' : IDL.Func([IDL.Nat64], [Result_2], []),
-    'get_admin' : IDL.Func([], [IDL.Nat64], ['query']),
+    'delete_ship' : IDL.Func([IDL.Nat64], [Result_2], []),
+    'delete_user' : IDL.Func([IDL.Nat64], [Result_3], []),
+    'enqueue_ship' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, PriorityClass],
+        [Result_8],
+        [],
+      ),
+    'find_free_berth_windows' : IDL.Func(
+        [IDL.Nat64, IDL.Float64, IDL.Float64, IDL.Nat64, IDL.Nat64, IDL.Nat64],
+        [Result_9],
+        ['query'],
+      ),
     'get_all_ports' : IDL.Func([], [IDL.Vec(Port)], ['query']),
+    'get_all_roles' : IDL.Func([], [Result_10], ['query']),
+    'get_all_ships' : IDL.Func([], [IDL.Vec(Ship)], ['query']),
     'get_all_users' : IDL.Func([], [IDL.Vec(User)], ['query']),
+    'get_anchorage_queue' : IDL.Func(
+        [IDL.Nat64],
+        [IDL.Vec(AnchorageEntry)],
+        ['query'],
+      ),
+    'get_approval_policy' : IDL.Func([], [ApprovalPolicy], ['query']),
+    'get_audit_log' : IDL.Func(
+        [AuditFilter, IDL.Opt(IDL.Nat64), IDL.Nat64],
+        [Result_11],
+        ['query'],
+      ),
+    'get_berth' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result], ['query']),
+    'get_berth_reservations' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Nat64],
+        [IDL.Vec(BerthReservation)],
+        ['query'],
+      ),
+    'get_certified_port' : IDL.Func([IDL.Nat64], [Result_12], ['query']),
+    'get_id_mode' : IDL.Func([], [IdMode], ['query']),
+    'get_metrics' : IDL.Func([], [Result_13], ['query']),
+    'get_my_role' : IDL.Func([], [IDL.Opt(Role)], ['query']),
     'get_port' : IDL.Func([IDL.Nat64], [Result_1], ['query']),
-    'get_user' : IDL.Func([IDL.Nat64], [Result_2], ['query']),
-    'ships_arrival' : IDL.Func([IDL.Nat64, IDL.Nat32], [Result], []),
-    'transfer_ships_admin' : IDL.Func(
-        [IDL.Nat64, IDL.Nat64, IDL.Nat32, IDL.Nat64],
+    'get_port_berths' : IDL.Func([IDL.Nat64], [IDL.Vec(Berth)], ['query']),
+    'get_port_call' : IDL.Func([IDL.Nat64], [Result_4], ['query']),
+    'get_port_calls' : IDL.Func([IDL.Nat64], [IDL.Vec(PortCall)], ['query']),
+    'get_port_movements' : IDL.Func(
+        [IDL.Nat64, IDL.Opt(IDL.Nat64), IDL.Opt(IDL.Nat64)],
+        [Result_14],
+        ['query'],
+      ),
+    'get_port_occupancy_at' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [Result_15],
+        ['query'],
+      ),
+    'get_port_ships' : IDL.Func([IDL.Nat64], [Result_16], ['query']),
+    'get_port_transfers' : IDL.Func([IDL.Nat64], [Result_17], ['query']),
+    'get_proposal' : IDL.Func([IDL.Nat64], [Result_6], ['query']),
+    'get_queue_position' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [Result_18],
+        ['query'],
+      ),
+    'get_reservation' : IDL.Func([IDL.Nat64], [Result_7], ['query']),
+    'get_ship' : IDL.Func([IDL.Nat64], [Result_2], ['query']),
+    'get_transfer' : IDL.Func([IDL.Nat64], [Result_19], ['query']),
+    'get_user' : IDL.Func([IDL.Nat64], [Result_3], ['query']),
+    'grant_role' : IDL.Func([IDL.Principal, Role], [Result_20], []),
+    'http_request' : IDL.Func([HttpRequest], [HttpResponse], ['query']),
+    'leave_anchorage' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result_8], []),
+    'list_certified_ports' : IDL.Func(
+        [IDL.Opt(IDL.Nat64), IDL.Nat64],
+        [Result_21],
+        ['query'],
+      ),
+    'list_ports' : IDL.Func(
+        [IDL.Opt(IDL.Vec(IDL.Nat8)), IDL.Nat64, IDL.Opt(PortSort)],
+        [Result_22],
+        ['query'],
+      ),
+    'list_proposals' : IDL.Func(
+        [IDL.Opt(ProposalStatus), IDL.Opt(IDL.Nat64), IDL.Nat64],
+        [Result_23],
+        ['query'],
+      ),
+    'list_users' : IDL.Func(
+        [IDL.Opt(IDL.Nat64), IDL.Nat64],
+        [UserPage],
+        ['query'],
+      ),
+    'plan_berths' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(PlanRequest)],
+        [Result_24],
+        ['query'],
+      ),
+    'record_port_call_arrival' : IDL.Func([IDL.Nat64], [Result_4], []),
+    'record_port_call_berthing' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64],
+        [Result_4],
+        [],
+      ),
+    'record_port_call_departure' : IDL.Func([IDL.Nat64], [Result_4], []),
+    'register_me' : IDL.Func([UserPayload], [Result_3], []),
+    'reject_proposal' : IDL.Func([IDL.Nat64], [Result_6], []),
+    'release_berth' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result], []),
+    'reschedule_reservation' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Nat64],
+        [Result_7],
+        [],
+      ),
+    'reserve_berth' : IDL.Func([ReservationPayload], [Result_7], []),
+    'restore_port' : IDL.Func([IDL.Nat64], [Result_1], []),
+    'restore_user' : IDL.Func([IDL.Nat64], [Result_3], []),
+    'revoke_role' : IDL.Func([IDL.Principal], [Result_25], []),
+    'search_ports' : IDL.Func(
+        [PortSearch, IDL.Opt(IDL.Vec(IDL.Nat8)), IDL.Nat64],
+        [Result_22],
+        ['query'],
+      ),
+    'set_approval_policy' : IDL.Func([ApprovalPolicy], [Result_26], []),
+    'set_berth_maintenance' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, IDL.Bool],
+        [Result],
+        [],
+      ),
+    'set_id_mode' : IDL.Func([IdMode], [Result_27], []),
+    'ship_departure' : IDL.Func([IDL.Nat64, IDL.Nat64], [Result_2], []),
+    'ships_arrival' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(IDL.Nat64), IDL.Opt(AdmissionMode)],
+        [Result_28],
+        [],
+      ),
+    'ships_departure' : IDL.Func(
+        [IDL.Nat64, IDL.Vec(IDL.Nat64)],
+        [Result_29],
+        [],
+      ),
+    'transfer_ships_admin' : IDL.Func([TransferRequest], [Result_19], []),
+    'update_berth' : IDL.Func(
+        [IDL.Nat64, IDL.Nat64, BerthPayload],
         [Result],
         [],
       ),
-    'update_port' : IDL.Func([IDL.Nat64, PortPayload], [Result_1], []),
-    'update_user' : IDL.Func([IDL.Nat64, UserPayload], [Result_2], []),
+    'update_my_profile' : IDL.Func([UserPatch, IDL.Nat64], [Result_3], []),
+    'update_port' : IDL.Func([IDL.Nat64, PortPatch, IDL.Nat64], [Result_1], []),
+    'update_ship' : IDL.Func([IDL.Nat64, ShipPayload], [Result_2], []),
+    'update_user' : IDL.Func([IDL.Nat64, UserPatch, IDL.Nat64], [Result_3], []),
+    'whoami' : IDL.Func([], [Result_3], ['query']),
   });
 };
 export const init = ({ IDL }) => { return []; };
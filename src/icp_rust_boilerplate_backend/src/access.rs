@@ -0,0 +1,223 @@
+use crate::audit::{self, EntityKind};
+use crate::metrics::metered;
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+// Roles are ordered by privilege: a caller holding a role may do anything the
+// lower roles may do.
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Role {
+    Viewer,
+    Operator,
+    PortAuthority,
+    Admin,
+}
+
+impl Storable for Role {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Role {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Principal does not implement Storable, so role assignments are keyed by its raw bytes.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct StorablePrincipal(pub Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+impl BoundedStorable for StorablePrincipal {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RoleAssignment {
+    principal: Principal,
+    role: Role,
+}
+
+thread_local! {
+    static ROLE_STORAGE: RefCell<StableBTreeMap<StorablePrincipal, Role, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+}
+
+// grants the admin role to the principal that installed the canister.
+pub(crate) fn bootstrap(controller: Principal) {
+    if controller != Principal::anonymous() {
+        do_insert_role(controller, Role::Admin);
+    }
+}
+
+// returns the effective role of a principal. controllers are always admins so the
+// canister can't be locked out by revoking the last admin.
+pub(crate) fn role_of(principal: &Principal) -> Option<Role> {
+    if *principal == Principal::anonymous() {
+        return None;
+    }
+    if ic_cdk::api::is_controller(principal) {
+        return Some(Role::Admin);
+    }
+    ROLE_STORAGE.with(|service| service.borrow().get(&StorablePrincipal(*principal)))
+}
+
+// guard used by every update method: returns the caller if it holds at least `required`.
+pub(crate) fn require_role(required: Role) -> Result<Principal, Error> {
+    let caller = ic_cdk::caller();
+    check_role(caller, role_of(&caller), required)
+}
+
+fn check_role(caller: Principal, role: Option<Role>, required: Role) -> Result<Principal, Error> {
+    match role {
+        Some(role) if role >= required => Ok(caller),
+        _ => Err(Error::Unauthorized {
+            msg: format!("caller {} requires the {:?} role", caller, required),
+        }),
+    }
+}
+
+// guard for self-service methods: any caller that isn't anonymous.
+pub(crate) fn require_authenticated() -> Result<Principal, Error> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "the anonymous principal can't call this method".to_string(),
+        });
+    }
+    Ok(caller)
+}
+
+// guard for records bound to a principal: passes for the owner, or for a
+// caller holding at least `role`.
+pub(crate) fn require_owner_or_role(owner: Option<Principal>, role: Role) -> Result<Principal, Error> {
+    let caller = ic_cdk::caller();
+    if caller != Principal::anonymous() && owner == Some(caller) {
+        return Ok(caller);
+    }
+    require_role(role)
+}
+
+fn do_insert_role(principal: Principal, role: Role) {
+    ROLE_STORAGE.with(|service| service.borrow_mut().insert(StorablePrincipal(principal), role));
+}
+
+metered! {
+#[ic_cdk::update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), Error> {
+    require_role(Role::Admin)?;
+    if principal == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "roles can't be granted to the anonymous principal".to_string(),
+        });
+    }
+    let before = ROLE_STORAGE.with(|service| service.borrow().get(&StorablePrincipal(principal)));
+    do_insert_role(principal, role);
+    audit::record(
+        "grant_role",
+        EntityKind::Role,
+        None,
+        json!({ "principal": principal, "role": role }),
+        before.as_ref().and_then(audit::json),
+        audit::json(&role),
+    );
+    Ok(())
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn revoke_role(principal: Principal) -> Result<Role, Error> {
+    require_role(Role::Admin)?;
+    match ROLE_STORAGE.with(|service| service.borrow_mut().remove(&StorablePrincipal(principal))) {
+        Some(role) => {
+            audit::record(
+                "revoke_role",
+                EntityKind::Role,
+                None,
+                json!({ "principal": principal }),
+                audit::json(&role),
+                None,
+            );
+            Ok(role)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("principal {} has no role assigned", principal),
+        }),
+    }
+}
+}
+
+#[ic_cdk::query]
+fn get_my_role() -> Option<Role> {
+    role_of(&ic_cdk::caller())
+}
+
+#[ic_cdk::query]
+fn get_all_roles() -> Result<Vec<RoleAssignment>, Error> {
+    require_role(Role::Admin)?;
+    Ok(ROLE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(principal, role)| RoleAssignment { principal: principal.0, role })
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [Role; 4] = [Role::Viewer, Role::Operator, Role::PortAuthority, Role::Admin];
+
+    #[test]
+    fn roles_pass_the_guards_of_their_own_and_lower_roles() {
+        let caller = Principal::from_slice(&[1; 29]);
+        for (held, role) in ROLES.iter().enumerate() {
+            for (needed, required) in ROLES.iter().enumerate() {
+                let result = check_role(caller, Some(*role), *required);
+                if held >= needed {
+                    assert_eq!(result.ok(), Some(caller), "{:?} for {:?}", role, required);
+                } else {
+                    assert!(matches!(result, Err(Error::Unauthorized { .. })), "{:?} for {:?}", role, required);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn callers_without_a_role_pass_no_guard() {
+        let caller = Principal::from_slice(&[1; 29]);
+        for required in ROLES {
+            assert!(matches!(check_role(caller, None, required), Err(Error::Unauthorized { .. })));
+        }
+        // an anonymous installer isn't made admin
+        bootstrap(Principal::anonymous());
+        assert!(ROLE_STORAGE.with(|service| service.borrow().is_empty()));
+        // and anonymous callers have no role even if one was stored for them
+        do_insert_role(Principal::anonymous(), Role::Admin);
+        assert_eq!(role_of(&Principal::anonymous()), None);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::metrics::metered;
+use crate::movement;
+use crate::sequence::{self, Sequence};
+use crate::ship::{self, Ship, ShipType};
+use crate::{Error, Memory, Port, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+// Used for the wait estimate until the port has recorded a completed stay.
+const DEFAULT_DWELL_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Queue classes, served in this order; ships of the same class are served
+// first come, first served.
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PriorityClass {
+    Emergency,
+    Passenger,
+    Liner,
+    Tramp,
+}
+
+impl PriorityClass {
+    // the class a ship queues in when it's turned away by ships_arrival.
+    pub(crate) fn for_ship(ship: &Ship) -> PriorityClass {
+        match ship.ship_type {
+            ShipType::Passenger => PriorityClass::Passenger,
+            ShipType::Container | ShipType::RoRo => PriorityClass::Liner,
+            _ => PriorityClass::Tramp,
+        }
+    }
+
+    fn rank(self) -> u64 {
+        self as u64
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AnchorageEntry {
+    pub port_id: u64,
+    pub ship_id: u64,
+    pub class: PriorityClass,
+    pub queued_at: u64,
+    seq: u64,
+}
+
+impl AnchorageEntry {
+    fn key(&self) -> QueueKey {
+        ((self.port_id, self.class.rank()), self.seq)
+    }
+}
+
+impl Storable for AnchorageEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AnchorageEntry {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct QueuePosition {
+    entry: AnchorageEntry,
+    // 1-based place in the port's queue
+    position: u32,
+    estimated_wait: u64,
+}
+
+// ((port_id, class rank), seq): iterating a port's range yields its queue in
+// service order.
+type QueueKey = ((u64, u64), u64);
+
+thread_local! {
+    static ANCHORAGE_QUEUE: RefCell<StableBTreeMap<QueueKey, AnchorageEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+
+    // ship_id -> its queue key; a ship waits at one port at a time.
+    static QUEUED_SHIPS: RefCell<StableBTreeMap<u64, QueueKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_anchorage_queue(port_id: u64) -> Vec<AnchorageEntry> {
+    port_queue(port_id)
+}
+
+#[ic_cdk::query]
+fn get_queue_position(port_id: u64, ship_id: u64) -> Result<QueuePosition, Error> {
+    let port = match crate::_get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a port with id={} not found", port_id),
+            })
+        }
+    };
+    let queue = port_queue(port_id);
+    let index = match queue.iter().position(|entry| entry.ship_id == ship_id) {
+        Some(index) => index,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("ship with id={} is not waiting for port with id={}", ship_id, port_id),
+            })
+        }
+    };
+
+    let position = index as u64 + 1;
+    Ok(QueuePosition {
+        entry: queue[index].clone(),
+        position: position as u32,
+        estimated_wait: estimated_wait(&port, position),
+    })
+}
+
+// ships leave in waves of `capacity`, each taking about one average stay.
+fn estimated_wait(port: &Port, position: u64) -> u64 {
+    let free = port.capacity.saturating_sub(port.current_ships) as u64;
+    let waves = position.saturating_sub(free).div_ceil(port.capacity.max(1) as u64);
+    let dwell = movement::average_dwell(port.id).unwrap_or(DEFAULT_DWELL_NANOS);
+    waves.saturating_mul(dwell)
+}
+
+metered! {
+#[ic_cdk::update]
+fn enqueue_ship(port_id: u64, ship_id: u64, class: PriorityClass) -> Result<AnchorageEntry, Error> {
+    access::require_role(Role::Operator)?;
+    let port = match crate::_get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't queue a ship for port with id={}. port not found", port_id),
+            })
+        }
+    };
+    crate::check_open(&port)?;
+    match ship::_get_ship(&ship_id) {
+        Some(ship) if ship.current_port_id.is_some() => Err(Error::InvalidInput {
+            msg: format!("ship with id={} is already in a port", ship_id),
+            violations: Vec::new(),
+        }),
+        Some(ship) => {
+            // re-queueing moves the ship to the back of its (new) class
+            let before = queued_entry(ship.id).as_ref().and_then(audit::json);
+            remove_ship(ship.id);
+            let entry = push(port_id, &ship, class, time());
+            audit::record(
+                "enqueue_ship",
+                EntityKind::Anchorage,
+                Some(ship_id),
+                json!({ "port_id": port_id, "ship_id": ship_id, "class": class }),
+                before,
+                audit::json(&entry),
+            );
+            Ok(entry)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("couldn't queue a ship with id={}. ship not found", ship_id),
+        }),
+    }
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn leave_anchorage(port_id: u64, ship_id: u64) -> Result<AnchorageEntry, Error> {
+    access::require_role(Role::Operator)?;
+    match queued_entry(ship_id) {
+        Some(entry) if entry.port_id == port_id => {
+            remove_ship(ship_id);
+            audit::record(
+                "leave_anchorage",
+                EntityKind::Anchorage,
+                Some(ship_id),
+                json!({ "port_id": port_id, "ship_id": ship_id }),
+                audit::json(&entry),
+                None,
+            );
+            Ok(entry)
+        }
+        _ => Err(Error::NotFound {
+            msg: format!("ship with id={} is not waiting for port with id={}", ship_id, port_id),
+        }),
+    }
+}
+}
+
+// queues a ship at the back of its class.
+pub(crate) fn push(port_id: u64, ship: &Ship, class: PriorityClass, queued_at: u64) -> AnchorageEntry {
+    let entry = AnchorageEntry {
+        port_id,
+        ship_id: ship.id,
+        class,
+        queued_at,
+        seq: sequence::next(Sequence::Anchorage),
+    };
+    ANCHORAGE_QUEUE.with(|queue| queue.borrow_mut().insert(entry.key(), entry.clone()));
+    QUEUED_SHIPS.with(|index| index.borrow_mut().insert(entry.ship_id, entry.key()));
+    entry
+}
+
+// takes a ship out of whatever queue it's waiting in.
+pub(crate) fn remove_ship(ship_id: u64) {
+    if let Some(key) = QUEUED_SHIPS.with(|index| index.borrow_mut().remove(&ship_id)) {
+        ANCHORAGE_QUEUE.with(|queue| queue.borrow_mut().remove(&key));
+    }
+}
+
+// admits waiting ships, in queue order, while the port has room. called
+// whenever a ship leaves or the port's capacity grows.
+pub(crate) fn admit_waiting(port_id: u64) {
+    let room = match crate::_get_port(&port_id) {
+        Some(port) => port.capacity.saturating_sub(port.current_ships),
+        None => return,
+    };
+    for ship in take_waiting(port_id, room) {
+        ship::relocate_ship(ship, Some(port_id));
+    }
+}
+
+// takes up to `room` ships off the front of a port's queue. entries of ships
+// that no longer exist are dropped without taking up room.
+fn take_waiting(port_id: u64, room: u32) -> Vec<Ship> {
+    let mut ships = Vec::new();
+    while ships.len() < room as usize {
+        let next = ANCHORAGE_QUEUE.with(|queue| {
+            queue
+                .borrow()
+                .range(((port_id, 0), 0)..=((port_id, u64::MAX), u64::MAX))
+                .next()
+                .map(|(_, entry)| entry)
+        });
+        let entry = match next {
+            Some(entry) => entry,
+            None => break,
+        };
+        remove_ship(entry.ship_id);
+        ships.extend(ship::_get_ship(&entry.ship_id));
+    }
+    ships
+}
+
+// drops the queue of a port that is being removed.
+pub(crate) fn clear_port(port_id: u64) {
+    for entry in port_queue(port_id) {
+        remove_ship(entry.ship_id);
+    }
+}
+
+fn queued_entry(ship_id: u64) -> Option<AnchorageEntry> {
+    let key = QUEUED_SHIPS.with(|index| index.borrow().get(&ship_id))?;
+    ANCHORAGE_QUEUE.with(|queue| queue.borrow().get(&key))
+}
+
+pub(crate) fn port_queue(port_id: u64) -> Vec<AnchorageEntry> {
+    ANCHORAGE_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .range(((port_id, 0), 0)..=((port_id, u64::MAX), u64::MAX))
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ship(id: u64, ship_type: ShipType) -> Ship {
+        let ship = Ship {
+            id,
+            ship_type,
+            ..Default::default()
+        };
+        ship::do_insert_ship(&ship);
+        ship
+    }
+
+    fn queue(port_id: u64, ship: &Ship) -> AnchorageEntry {
+        push(port_id, ship, PriorityClass::for_ship(ship), 0)
+    }
+
+    fn ship_ids(entries: Vec<AnchorageEntry>) -> Vec<u64> {
+        entries.into_iter().map(|entry| entry.ship_id).collect()
+    }
+
+    fn port(capacity: u32, current_ships: u32) -> Port {
+        Port {
+            id: 1,
+            capacity,
+            current_ships,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn classes_are_served_in_priority_order_then_first_come() {
+        queue(1, &ship(10, ShipType::Bulk));
+        queue(1, &ship(11, ShipType::Container));
+        queue(1, &ship(12, ShipType::Passenger));
+        queue(1, &ship(13, ShipType::RoRo));
+        push(1, &ship(14, ShipType::Tanker), PriorityClass::Emergency, 0);
+        queue(2, &ship(15, ShipType::Passenger));
+
+        assert_eq!(ship_ids(port_queue(1)), [14, 12, 11, 13, 10]);
+        assert_eq!(ship_ids(port_queue(2)), [15]);
+    }
+
+    #[test]
+    fn requeueing_moves_a_ship_to_the_back() {
+        let first = ship(10, ShipType::Bulk);
+        queue(1, &first);
+        queue(1, &ship(11, ShipType::Bulk));
+        remove_ship(first.id);
+        queue(1, &first);
+
+        assert_eq!(ship_ids(port_queue(1)), [11, 10]);
+        assert_eq!(queued_entry(10).map(|entry| entry.port_id), Some(1));
+    }
+
+    #[test]
+    fn departures_admit_waiting_ships_in_queue_order() {
+        queue(1, &ship(10, ShipType::Bulk));
+        queue(1, &ship(11, ShipType::Passenger));
+        queue(1, &ship(12, ShipType::Container));
+        // a ship deleted while it waited doesn't take up room
+        queue(1, &Ship { id: 13, ship_type: ShipType::Passenger, ..Default::default() });
+
+        let admitted: Vec<u64> = take_waiting(1, 2).into_iter().map(|ship| ship.id).collect();
+        assert_eq!(admitted, [11, 12]);
+        assert_eq!(ship_ids(port_queue(1)), [10]);
+        assert!(take_waiting(1, 0).is_empty());
+        assert_eq!(queued_entry(11).map(|entry| entry.ship_id), None);
+    }
+
+    #[test]
+    fn waits_are_estimated_in_waves_of_capacity() {
+        // without a completed stay each wave takes a day
+        let day = DEFAULT_DWELL_NANOS;
+        assert_eq!(estimated_wait(&port(2, 1), 1), 0);
+        assert_eq!(estimated_wait(&port(2, 2), 1), day);
+        assert_eq!(estimated_wait(&port(2, 2), 2), day);
+        assert_eq!(estimated_wait(&port(2, 2), 3), 2 * day);
+        assert_eq!(estimated_wait(&port(2, 1), 4), 2 * day);
+        assert_eq!(estimated_wait(&port(0, 0), 1), day);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::access::{self, Role};
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Log, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Upper bound on entries examined while filtering one page, to stay within the
+// query instruction limit.
+const MAX_SCAN: u64 = 10_000;
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum EntityKind {
+    Port,
+    User,
+    Ship,
+    PortCall,
+    Berth,
+    Reservation,
+    Role,
+    // queue entries are identified by ship id
+    Anchorage,
+    // canister-wide settings; entries carry no entity id
+    Settings,
+    Transfer,
+    Proposal,
+}
+
+// One state-changing call. `args`, `before` and `after` are JSON; before/after
+// hold the affected record around the call (None when it didn't exist).
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    pub id: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub method: String,
+    pub entity_kind: EntityKind,
+    pub entity_id: Option<u64>,
+    pub args: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct AuditFilter {
+    entity_kind: Option<EntityKind>,
+    entity_id: Option<u64>,
+    caller: Option<Principal>,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.entity_kind.is_none_or(|kind| kind == entry.entity_kind)
+            && self.entity_id.is_none_or(|id| Some(id) == entry.entity_id)
+            && self.caller.is_none_or(|caller| caller == entry.caller)
+            && self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp <= to)
+    }
+}
+
+// Entries newest first. Pass `next_cursor` back as `cursor` to continue; it is
+// None once the start of the log has been reached.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditPage {
+    entries: Vec<AuditEntry>,
+    next_cursor: Option<u64>,
+    total: u64,
+}
+
+thread_local! {
+    static AUDIT_LOG: RefCell<Log<AuditEntry, Memory, Memory>> = RefCell::new(
+        Log::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))),
+        )
+        .expect("cannot init audit log")
+    );
+}
+
+// serializes a record for the before/after columns.
+pub(crate) fn json<T: serde::Serialize>(value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
+
+// appends an entry for the current call. a failed append traps, so a change is
+// never committed without its audit trail.
+pub(crate) fn record(
+    method: &str,
+    entity_kind: EntityKind,
+    entity_id: Option<u64>,
+    args: serde_json::Value,
+    before: Option<String>,
+    after: Option<String>,
+) {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let entry = AuditEntry {
+            id: log.len(),
+            caller: ic_cdk::caller(),
+            timestamp: time(),
+            method: method.to_string(),
+            entity_kind,
+            entity_id,
+            args: args.to_string(),
+            before,
+            after,
+        };
+        log.append(&entry).expect("cannot append to audit log");
+    });
+}
+
+#[ic_cdk::query]
+fn get_audit_log(filter: AuditFilter, cursor: Option<u64>, limit: u64) -> Result<AuditPage, Error> {
+    access::require_role(Role::Viewer)?;
+    Ok(page(&filter, cursor, limit))
+}
+
+fn page(filter: &AuditFilter, cursor: Option<u64>, limit: u64) -> AuditPage {
+    let limit = limit.clamp(1, crate::MAX_PAGE_SIZE);
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let total = log.len();
+        // cursor is one past the next index to examine
+        let mut index = cursor.unwrap_or(total).min(total);
+        let stop = index.saturating_sub(MAX_SCAN);
+        let mut entries = Vec::new();
+        while index > stop && (entries.len() as u64) < limit {
+            index -= 1;
+            if let Some(entry) = log.get(index) {
+                if filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+        AuditPage {
+            entries,
+            next_cursor: (index > 0).then_some(index),
+            total,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(entity_kind: EntityKind, entity_id: Option<u64>, caller: u8, timestamp: u64) {
+        AUDIT_LOG.with(|log| {
+            let log = log.borrow();
+            let entry = AuditEntry {
+                id: log.len(),
+                caller: Principal::from_slice(&[caller; 29]),
+                timestamp,
+                method: "test".to_string(),
+                entity_kind,
+                entity_id,
+                args: "{}".to_string(),
+                before: None,
+                after: None,
+            };
+            log.append(&entry).unwrap();
+        });
+    }
+
+    fn ids(page: &AuditPage) -> Vec<u64> {
+        page.entries.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn filters_combine_and_bound_timestamps_inclusively() {
+        append(EntityKind::Port, Some(1), 1, 10);
+        append(EntityKind::Ship, Some(1), 1, 20);
+        append(EntityKind::Port, Some(2), 2, 30);
+        append(EntityKind::Port, Some(1), 2, 40);
+        append(EntityKind::Settings, None, 1, 50);
+
+        let filtered = |filter: AuditFilter| ids(&page(&filter, None, 10));
+        assert_eq!(filtered(AuditFilter::default()), vec![4, 3, 2, 1, 0]);
+        let ports = AuditFilter {
+            entity_kind: Some(EntityKind::Port),
+            ..Default::default()
+        };
+        assert_eq!(filtered(ports.clone()), vec![3, 2, 0]);
+        assert_eq!(
+            filtered(AuditFilter {
+                entity_id: Some(1),
+                ..ports.clone()
+            }),
+            vec![3, 0]
+        );
+        assert_eq!(
+            filtered(AuditFilter {
+                caller: Some(Principal::from_slice(&[2; 29])),
+                ..ports
+            }),
+            vec![3, 2]
+        );
+        assert_eq!(
+            filtered(AuditFilter {
+                from: Some(20),
+                to: Some(40),
+                ..Default::default()
+            }),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn pages_run_newest_first_until_the_start_of_the_log() {
+        for timestamp in 0..5 {
+            append(EntityKind::Port, Some(timestamp % 2), 1, timestamp);
+        }
+        let filter = AuditFilter {
+            entity_id: Some(0),
+            ..Default::default()
+        };
+        let first = page(&filter, None, 2);
+        assert_eq!((ids(&first), first.next_cursor, first.total), (vec![4, 2], Some(2), 5));
+        let second = page(&filter, first.next_cursor, 2);
+        assert_eq!((ids(&second), second.next_cursor), (vec![0], None));
+        // a cursor past the end starts from the newest entry
+        assert_eq!(ids(&page(&AuditFilter::default(), Some(100), 1)), vec![4]);
+    }
+}
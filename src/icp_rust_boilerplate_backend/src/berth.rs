@@ -0,0 +1,513 @@
+use crate::access::{self, Role};
+use crate::anchorage;
+use crate::audit::{self, EntityKind};
+use crate::metrics::metered;
+use crate::port_call::{self, PortCallStatus};
+use crate::proposal::{self, Operation};
+use crate::reservation;
+use crate::sequence::{self, Sequence};
+use crate::ship::{self, Ship};
+use crate::validation;
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum BerthStatus {
+    Available,
+    Occupied,
+    Maintenance,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BerthOccupant {
+    pub ship_id: u64,
+    pub port_call_id: Option<u64>,
+}
+
+// Length and max draft are in metres; a ship fits if its LOA and draft don't
+// exceed them.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Berth {
+    pub id: u64,
+    pub port_id: u64,
+    pub name: String,
+    pub length: f64,
+    pub max_draft: f64,
+    pub status: BerthStatus,
+    pub occupant: Option<BerthOccupant>,
+}
+
+impl Berth {
+    pub(crate) fn fits(&self, ship: &Ship) -> bool {
+        ship.loa <= self.length && ship.draft <= self.max_draft
+    }
+}
+
+impl Storable for Berth {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Berth {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct BerthPayload {
+    pub name: String,
+    pub length: f64,
+    pub max_draft: f64,
+}
+
+// What gets put on a berth: a ship already in the port, or an arrived port
+// call, which then moves to Berthed.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum BerthAssignment {
+    Ship(u64),
+    PortCall(u64),
+}
+
+thread_local! {
+    // keyed by (port_id, berth_id) so a port's berths are stored together.
+    static BERTH_STORAGE: RefCell<StableBTreeMap<(u64, u64), Berth, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_berth(port_id: u64, berth_id: u64) -> Result<Berth, Error> {
+    match _get_berth(port_id, berth_id) {
+        Some(berth) => Ok(berth),
+        None => Err(Error::NotFound {
+            msg: format!("a berth with id={} not found in port with id={}", berth_id, port_id),
+        }),
+    }
+}
+
+#[ic_cdk::query]
+fn get_port_berths(port_id: u64) -> Vec<Berth> {
+    port_berths(port_id)
+}
+
+metered! {
+#[ic_cdk::update]
+fn add_berth(port_id: u64, payload: BerthPayload) -> Result<Berth, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let port = match crate::_get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't add a berth to port with id={}. port not found", port_id),
+            })
+        }
+    };
+    crate::check_open(&port)?;
+    validation::validate_berth(&payload)?;
+    check_capacity_change(port_id, berth_count(port_id) + 1)?;
+    let args = json!({ "port_id": port_id, "payload": &payload });
+
+    let berth = Berth {
+        id: sequence::next_id(Sequence::Berth),
+        port_id,
+        name: payload.name.trim().to_string(),
+        length: payload.length,
+        max_draft: payload.max_draft,
+        status: BerthStatus::Available,
+        occupant: None,
+    };
+
+    do_insert_berth(&berth);
+    sync_port_capacity(port_id);
+    audit::record("add_berth", EntityKind::Berth, Some(berth.id), args, None, audit::json(&berth));
+    Ok(berth)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn update_berth(port_id: u64, berth_id: u64, payload: BerthPayload) -> Result<Berth, Error> {
+    access::require_role(Role::PortAuthority)?;
+    validation::validate_berth(&payload)?;
+    let args = json!({ "port_id": port_id, "berth_id": berth_id, "payload": &payload });
+    let mut berth = find_berth(port_id, berth_id)?;
+    let before = audit::json(&berth);
+    berth.name = payload.name.trim().to_string();
+    berth.length = payload.length;
+    berth.max_draft = payload.max_draft;
+    check_ships_fit(&berth)?;
+    do_insert_berth(&berth);
+    audit::record("update_berth", EntityKind::Berth, Some(berth_id), args, before, audit::json(&berth));
+    Ok(berth)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn delete_berth(port_id: u64, berth_id: u64) -> Result<Berth, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let berth = find_berth(port_id, berth_id)?;
+    if berth.occupant.is_some() {
+        return Err(Error::InvalidInput {
+            msg: format!("berth with id={} is occupied", berth_id),
+            violations: Vec::new(),
+        });
+    }
+    if !reservation::active_reservations(berth_id, ic_cdk::api::time(), u64::MAX).is_empty() {
+        return Err(Error::Conflict {
+            msg: format!("berth with id={} has upcoming reservations", berth_id),
+        });
+    }
+    check_capacity_change(port_id, berth_count(port_id) - 1)?;
+
+    BERTH_STORAGE.with(|service| service.borrow_mut().remove(&(port_id, berth_id)));
+    sync_port_capacity(port_id);
+    audit::record(
+        "delete_berth",
+        EntityKind::Berth,
+        Some(berth_id),
+        json!({ "port_id": port_id, "berth_id": berth_id }),
+        audit::json(&berth),
+        None,
+    );
+    Ok(berth)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn set_berth_maintenance(port_id: u64, berth_id: u64, maintenance: bool) -> Result<Berth, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let mut berth = find_berth(port_id, berth_id)?;
+    if berth.occupant.is_some() {
+        return Err(Error::InvalidInput {
+            msg: format!("berth with id={} is occupied", berth_id),
+            violations: Vec::new(),
+        });
+    }
+    let before = audit::json(&berth);
+    berth.status = if maintenance {
+        BerthStatus::Maintenance
+    } else {
+        BerthStatus::Available
+    };
+    do_insert_berth(&berth);
+    audit::record(
+        "set_berth_maintenance",
+        EntityKind::Berth,
+        Some(berth_id),
+        json!({ "port_id": port_id, "berth_id": berth_id, "maintenance": maintenance }),
+        before,
+        audit::json(&berth),
+    );
+    Ok(berth)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn allocate_berth(port_id: u64, berth_id: u64, assignment: BerthAssignment) -> Result<Berth, Error> {
+    access::require_role(Role::Operator)?;
+    let args = json!({ "port_id": port_id, "berth_id": berth_id, "assignment": &assignment });
+    let before = _get_berth(port_id, berth_id).as_ref().and_then(audit::json);
+    let berth = allocate(port_id, berth_id, assignment)?;
+    audit::record("allocate_berth", EntityKind::Berth, Some(berth_id), args, before, audit::json(&berth));
+    Ok(berth)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn release_berth(port_id: u64, berth_id: u64) -> Result<Berth, Error> {
+    access::require_role(Role::Operator)?;
+    let mut berth = find_berth(port_id, berth_id)?;
+    if berth.occupant.is_none() {
+        return Err(Error::InvalidInput {
+            msg: format!("berth with id={} is not occupied", berth_id),
+            violations: Vec::new(),
+        });
+    }
+    let before = audit::json(&berth);
+    berth.occupant = None;
+    berth.status = BerthStatus::Available;
+    do_insert_berth(&berth);
+    audit::record(
+        "release_berth",
+        EntityKind::Berth,
+        Some(berth_id),
+        json!({ "port_id": port_id, "berth_id": berth_id }),
+        before,
+        audit::json(&berth),
+    );
+    Ok(berth)
+}
+}
+
+// puts a ship or an arrived port call on a free berth it physically fits.
+pub(crate) fn allocate(port_id: u64, berth_id: u64, assignment: BerthAssignment) -> Result<Berth, Error> {
+    let mut berth = find_berth(port_id, berth_id)?;
+    match berth.status {
+        BerthStatus::Available => (),
+        BerthStatus::Occupied => {
+            return Err(Error::InvalidInput {
+                msg: format!("berth with id={} is already occupied", berth_id),
+                violations: Vec::new(),
+            })
+        }
+        BerthStatus::Maintenance => {
+            return Err(Error::InvalidInput {
+                msg: format!("berth with id={} is under maintenance", berth_id),
+                violations: Vec::new(),
+            })
+        }
+    }
+
+    // Resolve the ship and, for port calls, check the call is ready to berth
+    let (ship_id, call) = match assignment {
+        BerthAssignment::Ship(ship_id) => (ship_id, None),
+        BerthAssignment::PortCall(call_id) => {
+            let call = match port_call::_get_port_call(&call_id) {
+                Some(call) => call,
+                None => {
+                    return Err(Error::NotFound {
+                        msg: format!("a port call with id={} not found", call_id),
+                    })
+                }
+            };
+            if call.port_id != port_id || call.status != PortCallStatus::Arrived {
+                return Err(Error::InvalidInput {
+                    msg: format!("port call with id={} hasn't arrived at port with id={}", call_id, port_id),
+                    violations: Vec::new(),
+                });
+            }
+            (call.ship_id, Some(call))
+        }
+    };
+    let ship = match ship::_get_ship(&ship_id) {
+        Some(ship) => ship,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a ship with id={} not found", ship_id),
+            })
+        }
+    };
+    if ship.current_port_id != Some(port_id) {
+        return Err(Error::InvalidInput {
+            msg: format!("ship with id={} is not in port with id={}", ship_id, port_id),
+            violations: Vec::new(),
+        });
+    }
+    if let Some(other) = berth_of_ship(port_id, ship_id) {
+        return Err(Error::InvalidInput {
+            msg: format!("ship with id={} is already on berth with id={}", ship_id, other.id),
+            violations: Vec::new(),
+        });
+    }
+    if !berth.fits(&ship) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "ship with id={} (loa {}m, draft {}m) doesn't fit berth with id={} (length {}m, max draft {}m)",
+                ship_id, ship.loa, ship.draft, berth_id, berth.length, berth.max_draft
+            ),
+            violations: Vec::new(),
+        });
+    }
+
+    if let Some(call) = call {
+        port_call::mark_berthed(call);
+    }
+    berth.status = BerthStatus::Occupied;
+    berth.occupant = Some(BerthOccupant {
+        ship_id,
+        port_call_id: match assignment {
+            BerthAssignment::PortCall(call_id) => Some(call_id),
+            BerthAssignment::Ship(_) => None,
+        },
+    });
+    do_insert_berth(&berth);
+    Ok(berth)
+}
+
+// frees the berth a ship occupies in the port, if any. called when the ship leaves.
+pub(crate) fn vacate_ship(port_id: u64, ship_id: u64) {
+    if let Some(mut berth) = berth_of_ship(port_id, ship_id) {
+        berth.occupant = None;
+        berth.status = BerthStatus::Available;
+        do_insert_berth(&berth);
+    }
+}
+
+pub(crate) fn port_berths(port_id: u64) -> Vec<Berth> {
+    BERTH_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((port_id, 0)..=(port_id, u64::MAX))
+            .map(|(_, berth)| berth)
+            .collect()
+    })
+}
+
+pub(crate) fn berth_count(port_id: u64) -> u32 {
+    BERTH_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((port_id, 0)..=(port_id, u64::MAX))
+            .count() as u32
+    })
+}
+
+// a port with berths has one ship slot per berth.
+fn sync_port_capacity(port_id: u64) {
+    if let Some(mut port) = crate::_get_port(&port_id) {
+        port.capacity = berth_count(port_id);
+        crate::store_port(&port);
+        anchorage::admit_waiting(port_id);
+    }
+}
+
+// the berths replace the port's capacity, so adding or removing one changes
+// the capacity: it needs approval when the policy asks for it, and may not
+// leave fewer berths than the port has ships.
+fn check_capacity_change(port_id: u64, berths: u32) -> Result<(), Error> {
+    proposal::require_direct(Operation::ChangeCapacity)?;
+    match crate::_get_port(&port_id) {
+        Some(port) if berths < port.current_ships => Err(Error::CapacityExceeded {
+            msg: format!(
+                "port with id={} has {} ships, more than the {} berths it would have",
+                port_id, port.current_ships, berths
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// the ship on a resized berth and the ships booked on it must still fit.
+fn check_ships_fit(berth: &Berth) -> Result<(), Error> {
+    if let Some(ship) = berth.occupant.as_ref().and_then(|occupant| ship::_get_ship(&occupant.ship_id)) {
+        if !berth.fits(&ship) {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "ship with id={} (loa {}m, draft {}m) on berth with id={} wouldn't fit it",
+                    ship.id, ship.loa, ship.draft, berth.id
+                ),
+                violations: Vec::new(),
+            });
+        }
+    }
+    for reservation in reservation::active_reservations(berth.id, ic_cdk::api::time(), u64::MAX) {
+        if let Some(ship) = ship::_get_ship(&reservation.ship_id).filter(|ship| !berth.fits(ship)) {
+            return Err(Error::Conflict {
+                msg: format!(
+                    "ship with id={} booked by reservation with id={} wouldn't fit berth with id={}",
+                    ship.id, reservation.id, berth.id
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn berth_of_ship(port_id: u64, ship_id: u64) -> Option<Berth> {
+    port_berths(port_id).into_iter().find(|berth| {
+        berth
+            .occupant
+            .as_ref()
+            .is_some_and(|occupant| occupant.ship_id == ship_id)
+    })
+}
+
+// a helper method to get a berth by port and id.
+pub(crate) fn _get_berth(port_id: u64, berth_id: u64) -> Option<Berth> {
+    BERTH_STORAGE.with(|service| service.borrow().get(&(port_id, berth_id)))
+}
+
+fn find_berth(port_id: u64, berth_id: u64) -> Result<Berth, Error> {
+    _get_berth(port_id, berth_id).ok_or_else(|| Error::NotFound {
+        msg: format!("a berth with id={} not found in port with id={}", berth_id, port_id),
+    })
+}
+
+// helper method to perform berth insert.
+pub(crate) fn do_insert_berth(berth: &Berth) {
+    BERTH_STORAGE.with(|service| service.borrow_mut().insert((berth.port_id, berth.id), berth.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(name: &str, length: f64, max_draft: f64) -> BerthPayload {
+        BerthPayload {
+            name: name.to_string(),
+            length,
+            max_draft,
+        }
+    }
+
+    #[test]
+    fn the_largest_valid_berth_fits_its_storage_bound() {
+        let payload = payload(&"泊".repeat(64), 500.0, 25.0);
+        assert!(validation::validate_berth(&payload).is_ok());
+        let berth = Berth {
+            id: u64::MAX,
+            port_id: u64::MAX,
+            name: payload.name,
+            length: payload.length,
+            max_draft: payload.max_draft,
+            status: BerthStatus::Maintenance,
+            occupant: Some(BerthOccupant {
+                ship_id: u64::MAX,
+                port_call_id: Some(u64::MAX),
+            }),
+        };
+        assert!(berth.to_bytes().len() <= Berth::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn berths_need_a_short_name_and_positive_finite_dimensions() {
+        let violations = |payload: &BerthPayload| match validation::validate_berth(payload) {
+            Ok(()) => Vec::new(),
+            Err(Error::InvalidInput { violations, .. }) => violations.into_iter().map(|v| v.field).collect(),
+            Err(_) => panic!("expected InvalidInput"),
+        };
+        assert!(violations(&payload("  Quay 1 ", 300.0, 15.0)).is_empty());
+        assert_eq!(violations(&payload("   ", f64::INFINITY, -1.0)), vec!["name", "length", "max_draft"]);
+        assert_eq!(violations(&payload(&"x".repeat(65), f64::NAN, 0.0)), vec!["name", "length", "max_draft"]);
+    }
+
+    #[test]
+    fn berth_changes_are_capacity_changes() {
+        crate::PORT_STORAGE.with(|service| {
+            service.borrow_mut().insert(
+                1,
+                crate::Port {
+                    id: 1,
+                    capacity: 2,
+                    current_ships: 2,
+                    ..Default::default()
+                },
+            )
+        });
+        assert!(check_capacity_change(1, 3).is_ok());
+        assert!(matches!(check_capacity_change(1, 1), Err(Error::CapacityExceeded { .. })));
+
+        // with capacity changes behind approval, berths can't be added or
+        // removed directly either
+        let policy = proposal::ApprovalPolicy {
+            operations: vec![Operation::ChangeCapacity],
+            ..Default::default()
+        };
+        proposal::APPROVAL_POLICY.with(|cell| cell.borrow_mut().set(policy)).unwrap();
+        assert!(matches!(check_capacity_change(1, 3), Err(Error::Unauthorized { .. })));
+    }
+}
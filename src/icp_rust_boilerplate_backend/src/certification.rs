@@ -0,0 +1,257 @@
+use crate::{Error, Port, MAX_PAGE_SIZE, PORT_STORAGE};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use candid::Encode;
+use ic_certification::{fork, fork_hash, labeled, labeled_hash, pruned, AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+// Certified reads. The canister's certified data is the root hash of
+//
+//     fork(labeled("http_assets", path -> sha256(body)),
+//          labeled("ports", id -> candid encoding of the port))
+//
+// where ids are 8 big-endian bytes, so the ports subtree is in id order. The
+// ports subtree carries every record in full, occupancy included; a client
+// verifies the certificate, checks the witness's root hash against it and
+// decodes the port from the witnessed leaf. http_assets is what HTTP gateways
+// check `/ports/{id}` responses against.
+//
+// The trees live on the heap and are rebuilt from the port storage on init and
+// after every upgrade.
+
+const HTTP_ASSETS: &[u8] = b"http_assets";
+const PORTS: &[u8] = b"ports";
+
+#[derive(Default)]
+struct CertifiedTree {
+    assets: RbTree<String, Hash>,
+    ports: RbTree<[u8; 8], Vec<u8>>,
+}
+
+thread_local! {
+    static TREE: RefCell<CertifiedTree> = RefCell::new(CertifiedTree::default());
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CertifiedPort {
+    port: Port,
+    // the system certificate over the canister's certified data
+    certificate: Vec<u8>,
+    // the CBOR hash tree proving the port's record
+    witness: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CertifiedPortPage {
+    ports: Vec<Port>,
+    next_cursor: Option<u64>,
+    certificate: Vec<u8>,
+    // proves the page is every port from the cursor up to the last one
+    // returned, or up to the end when there is no next page
+    witness: Vec<u8>,
+}
+
+// a port with the certificate and witness that prove it. a port that doesn't
+// exist is NotFound without a proof.
+#[ic_cdk::query]
+fn get_certified_port(id: u64) -> Result<CertifiedPort, Error> {
+    let port = crate::get_port(id)?;
+    let certificate = data_certificate()?;
+    let witness = TREE.with(|tree| {
+        let tree = tree.borrow();
+        fork(
+            pruned(labeled_hash(HTTP_ASSETS, &tree.assets.root_hash())),
+            labeled(PORTS, tree.ports.witness(&id.to_be_bytes())),
+        )
+    });
+    Ok(CertifiedPort {
+        port,
+        certificate,
+        witness: encode_witness(&witness),
+    })
+}
+
+// ports in id order starting at `cursor`, with a witness covering the whole
+// range so clients can tell no port was left out.
+#[ic_cdk::query]
+fn list_certified_ports(cursor: Option<u64>, limit: u64) -> Result<CertifiedPortPage, Error> {
+    let certificate = data_certificate()?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let start = cursor.unwrap_or(0);
+    let mut ports: Vec<Port> = PORT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range(start..)
+            .take(limit + 1)
+            .map(|(_, port)| port)
+            .collect()
+    });
+    let next_cursor = if ports.len() > limit {
+        ports.pop().map(|port| port.id)
+    } else {
+        None
+    };
+    let last = match (&next_cursor, ports.last()) {
+        (Some(_), Some(port)) => port.id,
+        _ => u64::MAX,
+    };
+    let witness = TREE.with(|tree| {
+        let tree = tree.borrow();
+        fork(
+            pruned(labeled_hash(HTTP_ASSETS, &tree.assets.root_hash())),
+            labeled(PORTS, tree.ports.value_range(&start.to_be_bytes(), &last.to_be_bytes())),
+        )
+    });
+    Ok(CertifiedPortPage {
+        ports,
+        next_cursor,
+        certificate,
+        witness: encode_witness(&witness),
+    })
+}
+
+// brings the trees in line with a port that was stored, or removed when `port`
+// is None, and certifies the new root.
+pub(crate) fn update(id: u64, port: Option<&Port>) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        match port {
+            Some(port) => tree.insert(port),
+            None => tree.remove(id),
+        }
+        ic_cdk::api::set_certified_data(&tree.root_hash());
+    });
+}
+
+pub(crate) fn rebuild() {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        *tree = CertifiedTree::default();
+        PORT_STORAGE.with(|service| {
+            for (_, port) in service.borrow().iter() {
+                tree.insert(&port);
+            }
+        });
+        ic_cdk::api::set_certified_data(&tree.root_hash());
+    });
+}
+
+// the `IC-Certificate` header for a response at `path`, if that path is
+// certified. only valid for the exact body the path was certified with.
+pub(crate) fn http_header(path: &str) -> Option<(String, String)> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let witness = TREE.with(|tree| {
+        let tree = tree.borrow();
+        tree.assets.get(path.as_bytes())?;
+        Some(fork(
+            labeled(HTTP_ASSETS, tree.assets.witness(path.as_bytes())),
+            pruned(labeled_hash(PORTS, &tree.ports.root_hash())),
+        ))
+    })?;
+    Some((
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            BASE64.encode(certificate),
+            BASE64.encode(encode_witness(&witness))
+        ),
+    ))
+}
+
+impl CertifiedTree {
+    fn insert(&mut self, port: &Port) {
+        self.ports.insert(port.id.to_be_bytes(), Encode!(port).unwrap());
+        self.assets
+            .insert(port_path(port.id), Sha256::digest(crate::http::port_body(port)).into());
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.ports.delete(&id.to_be_bytes());
+        self.assets.delete(port_path(id).as_bytes());
+    }
+
+    fn root_hash(&self) -> Hash {
+        fork_hash(
+            &labeled_hash(HTTP_ASSETS, &self.assets.root_hash()),
+            &labeled_hash(PORTS, &self.ports.root_hash()),
+        )
+    }
+}
+
+fn port_path(id: u64) -> String {
+    format!("/ports/{}", id)
+}
+
+// the certificate is only handed out to non-replicated queries.
+fn data_certificate() -> Result<Vec<u8>, Error> {
+    match ic_cdk::api::data_certificate() {
+        Some(certificate) => Ok(certificate),
+        None => Err(Error::Conflict {
+            msg: "no certificate is available; call this method as a query".to_string(),
+        }),
+    }
+}
+
+// self-describing CBOR, as the HTTP gateway and agents expect.
+fn encode_witness(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::Serializer::new(Vec::new());
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Decode;
+    use ic_certification::LookupResult;
+
+    fn port(id: u64, current_ships: u32) -> Port {
+        Port {
+            id,
+            name: format!("port {}", id),
+            location: "Rotterdam".to_string(),
+            capacity: 10,
+            current_ships,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn witnesses_prove_ports_against_the_certified_root() {
+        let mut tree = CertifiedTree::default();
+        for id in [1, 2, 300] {
+            tree.insert(&port(id, 0));
+        }
+        tree.insert(&port(2, 4));
+        tree.remove(300);
+
+        let witness = fork(
+            pruned(labeled_hash(HTTP_ASSETS, &tree.assets.root_hash())),
+            labeled(PORTS, tree.ports.witness(&2u64.to_be_bytes())),
+        );
+        assert_eq!(witness.digest(), tree.root_hash());
+        let leaf = match witness.lookup_path([PORTS, &2u64.to_be_bytes()[..]]) {
+            LookupResult::Found(leaf) => leaf,
+            _ => panic!("port 2 is not in the witness"),
+        };
+        assert_eq!(Decode!(leaf, Port).unwrap().current_ships, 4);
+        assert!(matches!(
+            witness.lookup_path([PORTS, &300u64.to_be_bytes()[..]]),
+            LookupResult::Absent
+        ));
+
+        let witness = fork(
+            labeled(HTTP_ASSETS, tree.assets.witness(b"/ports/1")),
+            pruned(labeled_hash(PORTS, &tree.ports.root_hash())),
+        );
+        assert_eq!(witness.digest(), tree.root_hash());
+        let body_hash: Hash = Sha256::digest(crate::http::port_body(&port(1, 0))).into();
+        assert!(matches!(
+            witness.lookup_path([HTTP_ASSETS, b"/ports/1"]),
+            LookupResult::Found(hash) if hash == body_hash
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{Error, Memory};
+use candid::{CandidType, Encode};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, marker::PhantomData, ops::RangeBounds};
+
+// Records are stored as runs of chunks of up to this many bytes.
+const CHUNK_SIZE: usize = 512;
+
+// Largest encoded payload callers may submit for a record.
+pub(crate) const MAX_RECORD_SIZE: usize = 64 * 1024;
+
+// A map from ids to records of any size. StableBTreeMap values must fit a fixed
+// bound, so each record is split into chunks keyed by (id, chunk index).
+pub(crate) struct ChunkedMap<V: Storable> {
+    // id -> number of chunks
+    records: StableBTreeMap<u64, u32, Memory>,
+    chunks: StableBTreeMap<(u64, u32), Blob<CHUNK_SIZE>, Memory>,
+    _record: PhantomData<V>,
+}
+
+impl<V: Storable> ChunkedMap<V> {
+    pub(crate) fn init(records: Memory, chunks: Memory) -> Self {
+        ChunkedMap {
+            records: StableBTreeMap::init(records),
+            chunks: StableBTreeMap::init(chunks),
+            _record: PhantomData,
+        }
+    }
+
+    pub(crate) fn get(&self, id: &u64) -> Option<V> {
+        let count = self.records.get(id)?;
+        Some(self.read(*id, count))
+    }
+
+    pub(crate) fn contains_key(&self, id: &u64) -> bool {
+        self.records.contains_key(id)
+    }
+
+    // stores a record, returning the one it replaced.
+    pub(crate) fn insert(&mut self, id: u64, record: V) -> Option<V> {
+        let previous = self.remove(&id);
+        let bytes = record.to_bytes();
+        let mut count = 0;
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            let chunk = Blob::try_from(chunk).expect("chunk within bounds");
+            self.chunks.insert((id, count), chunk);
+            count += 1;
+        }
+        self.records.insert(id, count);
+        previous
+    }
+
+    pub(crate) fn remove(&mut self, id: &u64) -> Option<V> {
+        let count = self.records.remove(id)?;
+        let record = self.read(*id, count);
+        for index in 0..count {
+            self.chunks.remove(&(*id, index));
+        }
+        Some(record)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.records.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u64, V)> + '_ {
+        self.records.iter().map(move |(id, count)| (id, self.read(id, count)))
+    }
+
+    pub(crate) fn range(&self, ids: impl RangeBounds<u64>) -> impl Iterator<Item = (u64, V)> + '_ {
+        self.records.range(ids).map(move |(id, count)| (id, self.read(id, count)))
+    }
+
+    // ids in the range, without loading their records.
+    pub(crate) fn keys(&self, ids: impl RangeBounds<u64>) -> impl Iterator<Item = u64> + '_ {
+        self.records.range(ids).map(|(id, _)| id)
+    }
+
+    fn read(&self, id: u64, count: u32) -> V {
+        let mut bytes = Vec::with_capacity(count as usize * CHUNK_SIZE);
+        for (_, chunk) in self.chunks.range((id, 0)..(id, count)) {
+            bytes.extend_from_slice(chunk.as_slice());
+        }
+        V::from_bytes(Cow::Owned(bytes))
+    }
+}
+
+// refuses payloads that encode to more than MAX_RECORD_SIZE bytes, before any
+// id is drawn or record written.
+pub(crate) fn check_size<T: CandidType>(kind: &str, payload: &T) -> Result<(), Error> {
+    let size = Encode!(payload).map(|bytes| bytes.len()).unwrap_or(usize::MAX);
+    if size > MAX_RECORD_SIZE {
+        return Err(Error::InvalidInput {
+            msg: format!("{} payload is {} bytes, above the limit of {} bytes", kind, size, MAX_RECORD_SIZE),
+            violations: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Port;
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn map() -> ChunkedMap<Port> {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        ChunkedMap::init(manager.get(MemoryId::new(0)), manager.get(MemoryId::new(1)))
+    }
+
+    fn port(id: u64, name_len: usize) -> Port {
+        Port {
+            id,
+            name: "n".repeat(name_len),
+            location: "Rotterdam".to_string(),
+            capacity: 4,
+            current_ships: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_above_the_old_bound_round_trip() {
+        let mut ports = map();
+        ports.insert(1, port(1, 5_000));
+        ports.insert(2, port(2, 10));
+        assert_eq!(ports.get(&1).map(|port| port.name.len()), Some(5_000));
+        assert_eq!(ports.get(&2).map(|port| port.name.len()), Some(10));
+        assert_eq!(ports.len(), 2);
+    }
+
+    #[test]
+    fn shrinking_and_removing_a_record_drops_its_chunks() {
+        let mut ports = map();
+        ports.insert(1, port(1, 5_000));
+        let previous = ports.insert(1, port(1, 3));
+        assert_eq!(previous.map(|port| port.name.len()), Some(5_000));
+        assert_eq!(ports.get(&1).map(|port| port.name), Some("nnn".to_string()));
+        assert_eq!(ports.chunks.len(), 1);
+
+        assert!(ports.remove(&1).is_some());
+        assert!(ports.get(&1).is_none());
+        assert_eq!(ports.chunks.len(), 0);
+    }
+
+    #[test]
+    fn oversized_payloads_are_refused() {
+        assert!(check_size("port", &"x".repeat(MAX_RECORD_SIZE)).is_err());
+        assert!(check_size("port", &"x".repeat(100)).is_ok());
+    }
+}
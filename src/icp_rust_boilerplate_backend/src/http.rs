@@ -0,0 +1,566 @@
+use crate::certification;
+use crate::metrics;
+use crate::port_index::{PortSearch, PortSort};
+use crate::ship;
+use crate::user_index;
+use crate::{Error, Port, User, MAX_PAGE_SIZE};
+use serde_json::{json, Value};
+
+// Read-only REST-style views for tooling that can't speak Candid. Every route
+// answers JSON, or CSV with `?format=csv`; list routes page with `limit` and
+// `cursor` and report the next cursor and the total in headers as well. Ids
+// are JSON strings: obscured ids don't fit the 53 bits a JSON number holds
+// exactly in most clients.
+
+const PORT_COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "location",
+    "capacity",
+    "current_ships",
+    "archived_at",
+    "created_at",
+    "updated_at",
+    "version",
+];
+const SHIP_COLUMNS: &[&str] = &[
+    "id",
+    "imo_number",
+    "name",
+    "flag",
+    "ship_type",
+    "loa",
+    "beam",
+    "draft",
+    "gross_tonnage",
+    "current_port_id",
+];
+// no emails or owners: the gateway calls anonymously, so this view is public
+const USER_COLUMNS: &[&str] = &[
+    "user_id",
+    "username",
+    "archived_at",
+    "created_at",
+    "updated_at",
+    "version",
+];
+const STATS_COLUMNS: &[&str] = &[
+    "ports",
+    "archived_ports",
+    "users",
+    "ships",
+    "ships_in_port",
+    "total_capacity",
+    "occupancy",
+];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+// A response before it's rendered in the requested format.
+struct View {
+    json: Value,
+    // the records making up the CSV form, one row each
+    columns: &'static [&'static str],
+    rows: Vec<Value>,
+    next_cursor: Option<String>,
+    total: Option<u64>,
+}
+
+impl View {
+    fn record(columns: &'static [&'static str], record: Value) -> View {
+        View {
+            json: record.clone(),
+            columns,
+            rows: vec![record],
+            next_cursor: None,
+            total: None,
+        }
+    }
+
+    fn page(
+        key: &str,
+        columns: &'static [&'static str],
+        rows: Vec<Value>,
+        next_cursor: Option<String>,
+        total: u64,
+    ) -> View {
+        View {
+            json: json!({ key: &rows, "next_cursor": &next_cursor, "total": total }),
+            columns,
+            rows,
+            next_cursor,
+            total: Some(total),
+        }
+    }
+}
+
+// The query string, decoded.
+struct Query(Vec<(String, String)>);
+
+impl Query {
+    fn parse(query: &str) -> Query {
+        Query(
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, value)) => (percent_decode(name), percent_decode(value)),
+                    None => (percent_decode(pair), String::new()),
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Error> {
+        match self.get(name) {
+            Some(value) => value.parse().map(Some).map_err(|_| Error::InvalidInput {
+                msg: format!("query parameter {} must be a non-negative number", name),
+                violations: Vec::new(),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn limit(&self) -> Result<u64, Error> {
+        Ok(self.number("limit")?.unwrap_or(MAX_PAGE_SIZE))
+    }
+}
+
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        let mut response = error_response(405, &format!("method {} is not allowed", request.method));
+        response.headers.push(("allow".to_string(), "GET".to_string()));
+        return response;
+    }
+    let (path, query) = match request.url.split_once('?') {
+        Some((path, query)) => (path, Query::parse(query)),
+        None => (request.url.as_str(), Query(Vec::new())),
+    };
+    let format = match query.get("format") {
+        None | Some("json") => Format::Json,
+        Some("csv") => Format::Csv,
+        Some(other) => return error_response(400, &format!("unknown format {}, expected json or csv", other)),
+    };
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let view = match segments.as_slice() {
+        ["ports"] => ports(&query),
+        ["ports", id] => parse_id(id).and_then(port),
+        ["ports", id, "ships"] => parse_id(id).and_then(|id| port_ships(id, &query)),
+        ["users"] => users(&query),
+        ["stats"] => Ok(stats()),
+        ["metrics"] => return metrics_response(),
+        _ => return error_response(404, &format!("no route for {}", path)),
+    };
+    match view {
+        Ok(view) => {
+            let mut response = render(view, format);
+            // a port's plain JSON is certified, so gateways can verify it
+            if matches!(segments.as_slice(), ["ports", _]) && query.0.is_empty() {
+                response.headers.extend(certification::http_header(path));
+            }
+            response
+        }
+        Err(error) => {
+            let (status_code, msg) = describe(error);
+            error_response(status_code, &msg)
+        }
+    }
+}
+
+// ports in id order, or in `sort` order; `location`, `name_prefix` and
+// `min_free_capacity` filter, and order, as search_ports does.
+fn ports(query: &Query) -> Result<View, Error> {
+    let limit = query.limit()?;
+    let cursor = match query.get("cursor") {
+        Some(cursor) => Some(hex_decode(cursor)?),
+        None => None,
+    };
+    let filter = PortSearch {
+        location: query.get("location").map(str::to_string),
+        name_prefix: query.get("name_prefix").map(str::to_string),
+        min_free_capacity: query.number("min_free_capacity")?,
+    };
+    let sort = match query.get("sort") {
+        None | Some("id") => PortSort::Id,
+        Some("name") => PortSort::Name,
+        Some("location") => PortSort::Location,
+        Some("occupancy") => PortSort::Occupancy,
+        Some("free_capacity") => PortSort::FreeCapacity,
+        Some(other) => {
+            return Err(Error::InvalidInput {
+                msg: format!("unknown sort {}", other),
+                violations: Vec::new(),
+            })
+        }
+    };
+    let filtered = filter.location.is_some() || filter.name_prefix.is_some() || filter.min_free_capacity.is_some();
+    let page = if filtered {
+        if sort != PortSort::Id {
+            return Err(Error::InvalidInput {
+                msg: "filtered ports come in search order and can't be sorted".to_string(),
+                violations: Vec::new(),
+            });
+        }
+        crate::search_ports(filter, cursor, limit)?
+    } else {
+        crate::list_ports(cursor, limit, Some(sort))?
+    };
+    Ok(View::page(
+        "ports",
+        PORT_COLUMNS,
+        page.ports.iter().map(to_json).collect(),
+        page.next_cursor.as_deref().map(hex_encode),
+        page.total,
+    ))
+}
+
+fn port(id: u64) -> Result<View, Error> {
+    crate::get_port(id).map(|port| View::record(PORT_COLUMNS, to_json(&port)))
+}
+
+// the body `/ports/{id}` answers with, which is what gets certified for it.
+pub(crate) fn port_body(port: &Port) -> Vec<u8> {
+    to_json(port).to_string().into_bytes()
+}
+
+// ships currently in a port, in id order.
+fn port_ships(port_id: u64, query: &Query) -> Result<View, Error> {
+    crate::get_port(port_id)?;
+    let limit = query.limit()?.clamp(1, MAX_PAGE_SIZE) as usize;
+    let cursor = query.number("cursor")?.unwrap_or(0);
+    let ship_ids = ship::ship_ids_in_port(port_id);
+    let mut page: Vec<u64> = ship_ids.iter().copied().filter(|id| *id >= cursor).take(limit + 1).collect();
+    let next_cursor = (page.len() > limit).then(|| page.remove(limit).to_string());
+    let ships = page.iter().filter_map(ship::_get_ship).map(|ship| to_json(&ship)).collect();
+    Ok(View::page("ships", SHIP_COLUMNS, ships, next_cursor, ship_ids.len() as u64))
+}
+
+// users in id order, or the one matching `username`. only the public fields
+// are shown, and users can't be looked up by email, which would tell anyone
+// whether an address is registered.
+fn users(query: &Query) -> Result<View, Error> {
+    let limit = query.limit()?;
+    if query.get("email").is_some() {
+        return Err(Error::InvalidInput {
+            msg: "users can't be looked up by email over HTTP".to_string(),
+            violations: Vec::new(),
+        });
+    }
+    let found = match query.get("username") {
+        Some(username) => user_index::find_by_username(username),
+        None => {
+            let page = crate::users_page(query.number("cursor")?, limit);
+            return Ok(View::page(
+                "users",
+                USER_COLUMNS,
+                page.users.iter().map(public_user).collect(),
+                page.next_cursor.map(|cursor| cursor.to_string()),
+                page.total,
+            ));
+        }
+    };
+    let users: Vec<Value> = found
+        .and_then(|user_id| crate::_get_user(&user_id))
+        .iter()
+        .map(public_user)
+        .collect();
+    let total = users.len() as u64;
+    Ok(View::page("users", USER_COLUMNS, users, None, total))
+}
+
+fn public_user(user: &User) -> Value {
+    ids_as_strings(json!({
+        "user_id": user.user_id,
+        "username": &user.username,
+        "archived_at": user.archived_at,
+        "created_at": user.created_at,
+        "updated_at": user.updated_at,
+        "version": user.version,
+    }))
+}
+
+fn stats() -> View {
+    let counts = metrics::counts();
+    View::record(
+        STATS_COLUMNS,
+        json!({
+            "ports": counts.ports,
+            "archived_ports": counts.archived_ports,
+            "users": counts.users,
+            "ships": counts.ships,
+            "ships_in_port": counts.ships_in_port,
+            "total_capacity": counts.total_capacity,
+            "occupancy": counts.occupancy(),
+        }),
+    )
+}
+
+// Prometheus scrapes plain text, whatever `format` asks for.
+fn metrics_response() -> HttpResponse {
+    HttpResponse {
+        status_code: 200,
+        headers: vec![(
+            "content-type".to_string(),
+            "text/plain; version=0.0.4; charset=utf-8".to_string(),
+        )],
+        body: metrics::prometheus().into_bytes(),
+    }
+}
+
+fn render(view: View, format: Format) -> HttpResponse {
+    let (content_type, body) = match format {
+        Format::Json => ("application/json", view.json.to_string()),
+        Format::Csv => ("text/csv; charset=utf-8", to_csv(view.columns, &view.rows)),
+    };
+    let mut headers = vec![("content-type".to_string(), content_type.to_string())];
+    if let Some(total) = view.total {
+        headers.push(("x-total-count".to_string(), total.to_string()));
+    }
+    if let Some(next_cursor) = view.next_cursor {
+        headers.push(("x-next-cursor".to_string(), next_cursor));
+    }
+    HttpResponse {
+        status_code: 200,
+        headers,
+        body: body.into_bytes(),
+    }
+}
+
+fn error_response(status_code: u16, msg: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("content-type".to_string(), "application/json".to_string())],
+        body: json!({ "error": msg }).to_string().into_bytes(),
+    }
+}
+
+// the HTTP status and message for an error of the Candid interface.
+fn describe(error: Error) -> (u16, String) {
+    match error {
+        Error::NotFound { msg } => (404, msg),
+        Error::Unauthorized { msg } => (403, msg),
+        Error::InvalidInput { msg, .. } => (400, msg),
+        Error::CapacityExceeded { msg } | Error::Conflict { msg } => (409, msg),
+        Error::Overflow { msg } => (500, msg),
+    }
+}
+
+fn parse_id(segment: &str) -> Result<u64, Error> {
+    segment.parse().map_err(|_| Error::InvalidInput {
+        msg: format!("{} is not a valid id", segment),
+        violations: Vec::new(),
+    })
+}
+
+fn to_json<T: serde::Serialize>(record: &T) -> Value {
+    ids_as_strings(serde_json::to_value(record).unwrap_or(Value::Null))
+}
+
+// turns `id` and `*_id` fields of a record into strings.
+fn ids_as_strings(mut record: Value) -> Value {
+    if let Value::Object(fields) = &mut record {
+        for (name, field) in fields.iter_mut() {
+            if name == "id" || name.ends_with("_id") {
+                if let Value::Number(id) = field {
+                    *field = Value::String(id.to_string());
+                }
+            }
+        }
+    }
+    record
+}
+
+// RFC 4180: a header row, then one row per record, fields quoted when needed.
+fn to_csv(columns: &[&str], rows: &[Value]) -> String {
+    let mut csv = columns.join(",");
+    csv.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|column| match row.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(text)) => csv_field(text),
+                Some(value) => csv_field(&value.to_string()),
+            })
+            .collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// decodes %XX escapes and '+' as space; malformed escapes are kept as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => match (hex_digit(bytes[index + 1]), hex_digit(bytes[index + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    index += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// port cursors are bytes, so they travel as hex in query strings.
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::InvalidInput {
+        msg: "cursor is not valid hex".to_string(),
+        violations: Vec::new(),
+    };
+    if !text.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::USER_STORAGE;
+    use candid::Principal;
+
+    fn get(url: &str) -> HttpResponse {
+        http_request(HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn users_route_leaves_out_emails_and_owners() {
+        let owner = Principal::from_slice(&[7; 29]);
+        let user = User {
+            user_id: 1,
+            username: "harbourmaster".to_string(),
+            email: "hm@example.com".to_string(),
+            owner: Some(owner),
+            ..Default::default()
+        };
+        USER_STORAGE.with(|service| service.borrow_mut().insert(user.user_id, user.clone()));
+        user_index::update(None, Some(&user));
+
+        for url in ["/users", "/users?username=harbourmaster", "/users?format=csv"] {
+            let response = get(url);
+            assert_eq!(response.status_code, 200, "{}", url);
+            let body = String::from_utf8(response.body).unwrap();
+            assert!(body.contains("harbourmaster"), "{}", url);
+            assert!(!body.contains("hm@example.com"), "{}", url);
+            assert!(!body.contains(&owner.to_text()), "{}", url);
+        }
+        assert_eq!(get("/users?email=hm@example.com").status_code, 400);
+        assert_eq!(get("/users?email=nobody@example.com").status_code, 400);
+    }
+
+    #[test]
+    fn query_strings_are_percent_decoded() {
+        let query = Query::parse("location=New+York&name_prefix=S%C3%A3o%20&limit=5&flag&bad=%zz");
+        assert_eq!(query.get("location"), Some("New York"));
+        assert_eq!(query.get("name_prefix"), Some("São "));
+        assert_eq!(query.number::<u64>("limit").ok().flatten(), Some(5));
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("bad"), Some("%zz"));
+        assert!(query.number::<u64>("bad").is_err());
+    }
+
+    #[test]
+    fn ids_are_strings_so_obscured_ids_survive_json_clients() {
+        let port = to_json(&Port {
+            id: u64::MAX,
+            capacity: 10,
+            ..Default::default()
+        });
+        assert_eq!(port["id"], json!("18446744073709551615"));
+        assert_eq!(port["capacity"], json!(10));
+        let ship = to_json(&ship::Ship {
+            id: 1 << 63,
+            current_port_id: Some(3),
+            ..Default::default()
+        });
+        assert_eq!((&ship["id"], &ship["current_port_id"]), (&json!("9223372036854775808"), &json!("3")));
+        assert_eq!(to_json(&ship::Ship::default())["current_port_id"], Value::Null);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let rows = vec![
+            json!({ "id": 1, "name": "Port, \"Main\"", "location": null }),
+            json!({ "id": 2, "name": "Harbour", "location": "Line\nbreak" }),
+        ];
+        let csv = to_csv(&["id", "name", "location"], &rows);
+        assert_eq!(
+            csv,
+            "id,name,location\r\n1,\"Port, \"\"Main\"\"\",\r\n2,Harbour,\"Line\nbreak\"\r\n"
+        );
+    }
+
+    #[test]
+    fn cursors_round_trip_through_hex() {
+        let cursor = vec![0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(hex_encode(&cursor), "007fabff");
+        assert_eq!(hex_decode("007fABff").ok(), Some(cursor));
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+    }
+}
@@ -1,14 +1,59 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-//use ic_cdk::api::time;
+use access::{Role, RoleAssignment};
+use anchorage::{AnchorageEntry, PriorityClass, QueuePosition};
+use audit::{AuditFilter, AuditPage, EntityKind};
+use berth::{Berth, BerthAssignment, BerthPayload};
+use certification::{CertifiedPort, CertifiedPortPage};
+use chunked::ChunkedMap;
+use http::{HttpRequest, HttpResponse};
+use candid::Principal;
+use ic_cdk::api::time;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{Cell, DefaultMemoryImpl, Storable};
+use metrics::{metered, Metrics};
+use movement::{MovementEvent, MovementKind};
+use planner::{BerthPlan, PlanRequest};
+use port_call::{PortCall, PortCallPayload};
+use port_index::{PortSearch, PortSort};
+use proposal::{ApprovalPolicy, Operation, Proposal, ProposalAction, ProposalPage, ProposalStatus};
+use reservation::{BerthReservation, FreeWindow, ReservationPayload};
+use sequence::{IdMode, Sequence};
+use serde_json::json;
+use ship::{Ship, ShipPayload};
+use transfer::{Transfer, TransferRequest};
+use validation::FieldViolation;
 use std::{borrow::Cow, cell::RefCell};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
 
+// Upper bound on records returned by one paginated query, to stay within the
+// query instruction limit and the response size cap.
+const MAX_PAGE_SIZE: u64 = 100;
+
+mod access;
+mod anchorage;
+mod audit;
+mod berth;
+mod certification;
+mod chunked;
+mod http;
+mod metrics;
+mod movement;
+mod planner;
+mod port_call;
+mod port_index;
+mod profile;
+mod proposal;
+mod reservation;
+mod schema;
+mod sequence;
+mod ship;
+mod transfer;
+mod user_index;
+mod validation;
+
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct Port {
     id: u64,
@@ -16,63 +61,79 @@ struct Port {
     location: String,
     capacity: u32,
     current_ships: u32,
+    // set while the port is archived: kept with its history, but closed to
+    // new ships and bookings
+    archived_at: Option<u64>,
+    created_at: u64,
+    // updated_at, updated_by and version follow edits to the port itself;
+    // occupancy and berth capacity change without touching them
+    updated_at: u64,
+    // None for records last changed before changes were tracked
+    updated_by: Option<Principal>,
+    // bumped by every edit; update_port takes the version it was based on
+    version: u64,
 }
 
+// Ports and users are stored in versioned envelopes, see schema.rs
 impl Storable for Port {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(schema::encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        schema::decode(bytes.as_ref())
     }
 }
 
-impl BoundedStorable for Port {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
-}
-
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct User {
     user_id: u64,
     username: String,
     email: String,
+    // the principal the user signs in as; None for users created without one
+    owner: Option<Principal>,
+    // set while the user is archived; archived users can't be changed
+    archived_at: Option<u64>,
+    created_at: u64,
+    updated_at: u64,
+    updated_by: Option<Principal>,
+    // bumped by every change; update_user and update_my_profile take the
+    // version they were based on
+    version: u64,
 }
 
 impl Storable for User {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(schema::encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        schema::decode(bytes.as_ref())
     }
 }
 
-impl BoundedStorable for User {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
-}
-
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
     );
 
+    // the shared id counter of earlier versions; only read now, as the
+    // starting point of the per-entity sequences
     static ID_COUNTER: RefCell<IdCell> = RefCell::new(
         IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
             .expect("Cannot create a counter")
     );
 
-    static PORT_STORAGE: RefCell<StableBTreeMap<u64, Port, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    // ports and users are unbounded in size, so they're chunked; memories 1
+    // and 2 held them in bounded maps before, see schema::migrate
+    static PORT_STORAGE: RefCell<ChunkedMap<Port>> = RefCell::new(ChunkedMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))),
     ));
 
-    static USER_STORAGE: RefCell<StableBTreeMap<u64, User, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+    static USER_STORAGE: RefCell<ChunkedMap<User>> = RefCell::new(ChunkedMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))),
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))),
     ));
 }
 
@@ -83,12 +144,94 @@ struct PortPayload {
     capacity: u32,
 }
 
+// How ships_arrival handles a batch that doesn't fit the remaining capacity.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+enum AdmissionMode {
+    // reject the whole batch with CapacityExceeded
+    #[default]
+    AllOrNothing,
+    // admit ships in the given order up to the remaining capacity and report the rest
+    Partial,
+    // admit up to the remaining capacity and put the rest in the anchorage queue.
+    // ships still docked in another port can't wait at anchor and are reported
+    // as rejected
+    Queue,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ArrivalReport {
+    admitted: Vec<u64>,
+    queued: Vec<u64>,
+    rejected: Vec<u64>,
+}
+
 #[derive(candid::CandidType, Serialize, Deserialize, Default)]
 struct UserPayload {
     username: String,
     email: String,
 }
 
+// Changes to a port; fields left as None keep their current value.
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct PortPatch {
+    name: Option<String>,
+    location: Option<String>,
+    capacity: Option<u32>,
+}
+
+// Changes to a user; fields left as None keep their current value.
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct UserPatch {
+    username: Option<String>,
+    email: Option<String>,
+}
+
+// Pass `next_cursor` back as `cursor` to get the following page; it is None on
+// the last page. `total` counts all ports, not just this page. search_ports
+// pages may come back short while there's more to scan, and their `total`
+// counts the ports searched: those in the range of the index the search walks,
+// or all ports when no filter is set.
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct PortPage {
+    ports: Vec<Port>,
+    next_cursor: Option<Vec<u8>>,
+    total: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct UserPage {
+    users: Vec<User>,
+    next_cursor: Option<u64>,
+    total: u64,
+}
+
+#[ic_cdk::init]
+fn init() {
+    access::bootstrap(ic_cdk::caller());
+    certification::rebuild();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // ships counted in a port before ships had records come back as
+    // placeholders, recorded as arriving now
+    for (ship, occupancy) in schema::migrate() {
+        let port_id = ship.current_port_id.unwrap_or_default();
+        movement::record(port_id, ship.id, MovementKind::Arrival, occupancy);
+        audit::record(
+            "post_upgrade",
+            EntityKind::Ship,
+            Some(ship.id),
+            json!({ "port_id": port_id, "reason": "placeholder for a ship counted before ships had records" }),
+            None,
+            audit::json(&ship),
+        );
+    }
+    port_index::rebuild();
+    user_index::rebuild();
+    certification::rebuild();
+}
+
 #[ic_cdk::query]
 fn get_port(id: u64) -> Result<Port, Error> {
     match _get_port(&id) {
@@ -101,113 +244,468 @@ fn get_port(id: u64) -> Result<Port, Error> {
 
 #[ic_cdk::query]
 fn get_user(user_id: u64) -> Result<User, Error> {
+    let (viewer, role) = viewer();
     match _get_user(&user_id) {
-        Some(user) => Ok(user),
+        Some(user) => Ok(visible_user(user, &viewer, role)),
         None => Err(Error::NotFound {
             msg: format!("a user with id={} not found", user_id),
         }),
     }
 }
 
+metered! {
 #[ic_cdk::update]
-fn add_port(port_payload: PortPayload) -> Option<Port> {
-    let port_id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment port id counter");
-
-    let port = Port {
-        id: port_id,
-        name: port_payload.name,
-        location: port_payload.location,
+fn add_port(port_payload: PortPayload) -> Result<Port, Error> {
+    access::require_role(Role::PortAuthority)?;
+    chunked::check_size("port", &port_payload)?;
+    validation::validate_port(&port_payload)?;
+    let args = json!({ "payload": &port_payload });
+    let mut port = Port {
+        id: sequence::next_id(Sequence::Port),
+        name: port_payload.name.trim().to_string(),
+        location: port_payload.location.trim().to_string(),
         capacity: port_payload.capacity,
         current_ships: 0,
+        created_at: time(),
+        ..Default::default()
     };
 
-    do_insert_port(&port);
-    Some(port)
+    do_insert_port(&mut port);
+    audit::record("add_port", EntityKind::Port, Some(port.id), args, None, audit::json(&port));
+    Ok(port)
+}
 }
 
+metered! {
 #[ic_cdk::update]
-fn add_user(user_payload: UserPayload) -> Option<User> {
-    let user_id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment user id counter");
+fn add_user(user_payload: UserPayload, owner: Option<Principal>) -> Result<User, Error> {
+    access::require_role(Role::Admin)?;
+    create_user("add_user", user_payload, owner)
+}
+}
 
-    let user = User {
-        user_id,
-        username: user_payload.username,
-        email: user_payload.email,
+// a helper method to validate and store a new user. used in add_user and
+// register_me.
+fn create_user(method: &str, payload: UserPayload, owner: Option<Principal>) -> Result<User, Error> {
+    chunked::check_size("user", &payload)?;
+    validation::validate_user(&payload, None)?;
+    if let Some(owner) = owner {
+        if owner == Principal::anonymous() {
+            return Err(Error::InvalidInput {
+                msg: "a user can't be owned by the anonymous principal".to_string(),
+                violations: Vec::new(),
+            });
+        }
+        if let Some(user_id) = user_index::find_by_owner(&owner) {
+            return Err(Error::Conflict {
+                msg: format!("principal {} already owns the user with id={}", owner, user_id),
+            });
+        }
+    }
+    let args = json!({ "payload": &payload, "owner": owner });
+    let mut user = User {
+        user_id: sequence::next_id(Sequence::User),
+        username: payload.username,
+        email: payload.email,
+        owner,
+        created_at: time(),
+        ..Default::default()
     };
 
-    do_insert_user(&user);
-    Some(user)
+    do_insert_user(&mut user);
+    audit::record(method, EntityKind::User, Some(user.user_id), args, None, audit::json(&user));
+    Ok(user)
 }
 
+metered! {
 #[ic_cdk::update]
-fn update_port(id: u64, payload: PortPayload) -> Result<Port, Error> {
-    match PORT_STORAGE.with(|service| service.borrow_mut().get(&id)) {
-        Some(mut port) => {
-            port.name = payload.name;
-            port.location = payload.location;
-            port.capacity = payload.capacity;
-            do_insert_port(&port);
-            Ok(port)
+fn update_port(id: u64, patch: PortPatch, expected_version: u64) -> Result<Port, Error> {
+    access::require_role(Role::PortAuthority)?;
+    if let Some(port) = _get_port(&id) {
+        check_version("port", id, port.version, expected_version)?;
+        if patch.capacity.is_some_and(|capacity| capacity != port.capacity) {
+            proposal::require_direct(Operation::ChangeCapacity)?;
+        }
+    }
+    apply_port_patch("update_port", id, patch)
+}
+}
+
+// a helper method to validate and apply a port patch. used in update_port and
+// by approved capacity proposals.
+fn apply_port_patch(method: &str, id: u64, patch: PortPatch) -> Result<Port, Error> {
+    chunked::check_size("port", &patch)?;
+    validation::validate_port_patch(&patch)?;
+    let mut port = match _get_port(&id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't update a port with id={}. port not found", id),
+            })
+        }
+    };
+    check_open(&port)?;
+    if let Some(capacity) = patch.capacity.filter(|capacity| *capacity != port.capacity) {
+        if capacity < port.current_ships {
+            return Err(Error::CapacityExceeded {
+                msg: format!(
+                    "capacity {} is below the {} ships currently in port with id={}",
+                    capacity, port.current_ships, id
+                ),
+            });
+        }
+        // ports with berths take their capacity from them
+        let berths = berth::berth_count(id);
+        if berths > 0 {
+            return Err(Error::InvalidInput {
+                msg: format!("capacity of port with id={} is derived from its {} berths", id, berths),
+                violations: Vec::new(),
+            });
         }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't update a port with id={}. port not found", id),
-        }),
     }
+
+    let before = audit::json(&port);
+    let mut changes = Vec::new();
+    let trimmed = |value: &Option<String>| value.as_deref().map(|value| value.trim().to_string());
+    patch_field(&mut changes, "name", &mut port.name, trimmed(&patch.name));
+    patch_field(&mut changes, "location", &mut port.location, trimmed(&patch.location));
+    patch_field(&mut changes, "capacity", &mut port.capacity, patch.capacity);
+    if changes.is_empty() {
+        return Ok(port);
+    }
+    do_insert_port(&mut port);
+    // extra capacity goes to ships waiting at anchor
+    anchorage::admit_waiting(id);
+    let port = _get_port(&id).unwrap_or(port);
+    audit::record(
+        method,
+        EntityKind::Port,
+        Some(id),
+        json!({ "id": id, "patch": &patch, "changes": changes }),
+        before,
+        audit::json(&port),
+    );
+    Ok(port)
 }
 
-// helper method to perform port insert.
-fn do_insert_port(port: &Port) {
-    PORT_STORAGE.with(|service| service.borrow_mut().insert(port.id, port.clone()));
+// sets a patched field that differs from its current value, noting the change
+// for the audit trail.
+fn patch_field<T: PartialEq + serde::Serialize>(
+    changes: &mut Vec<serde_json::Value>,
+    field: &str,
+    current: &mut T,
+    value: Option<T>,
+) {
+    if let Some(value) = value.filter(|value| value != current) {
+        changes.push(json!({ "field": field, "before": &*current, "after": &value }));
+        *current = value;
+    }
 }
 
+// helper method to perform port insert for an edit made by the caller. stamps
+// the change on the port, which moves its version on.
+fn do_insert_port(port: &mut Port) {
+    port.updated_at = time();
+    port.updated_by = Some(ic_cdk::caller());
+    port.version += 1;
+    store_port(port);
+}
+
+// stores a port without stamping it. used for bookkeeping derived from other
+// records, like occupancy and berth capacity, so it doesn't invalidate the
+// version clients edit against. keeps the port indexes and certified data in
+// sync.
+fn store_port(port: &Port) {
+    let previous = PORT_STORAGE.with(|service| service.borrow_mut().insert(port.id, port.clone()));
+    port_index::update(previous.as_ref(), Some(port));
+    certification::update(port.id, Some(port));
+}
+
+metered! {
 #[ic_cdk::update]
-fn update_user(user_id: u64, payload: UserPayload) -> Result<User, Error> {
-    match USER_STORAGE.with(|service| service.borrow_mut().get(&user_id)) {
-        Some(mut user) => {
-            user.username = payload.username;
-            user.email = payload.email;
-            do_insert_user(&user);
-            Ok(user)
+fn update_user(user_id: u64, patch: UserPatch, expected_version: u64) -> Result<User, Error> {
+    let user = match _get_user(&user_id) {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't update a user with id={}. user not found", user_id),
+            })
         }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't update a user with id={}. user not found", user_id),
-        }),
+    };
+    access::require_owner_or_role(user.owner, Role::Admin)?;
+    check_version("user", user_id, user.version, expected_version)?;
+    modify_user("update_user", user, patch)
+}
+}
+
+// a helper method to validate and apply a profile patch. used in update_user
+// and update_my_profile.
+fn modify_user(method: &str, mut user: User, patch: UserPatch) -> Result<User, Error> {
+    if user.archived_at.is_some() {
+        return Err(Error::Conflict {
+            msg: format!("user with id={} is archived", user.user_id),
+        });
+    }
+    chunked::check_size("user", &patch)?;
+    validation::validate_user_patch(&patch, user.user_id)?;
+    let before = audit::json(&user);
+    let mut changes = Vec::new();
+    patch_field(&mut changes, "username", &mut user.username, patch.username.clone());
+    patch_field(&mut changes, "email", &mut user.email, patch.email.clone());
+    if changes.is_empty() {
+        return Ok(user);
     }
+    do_insert_user(&mut user);
+    audit::record(
+        method,
+        EntityKind::User,
+        Some(user.user_id),
+        json!({ "user_id": user.user_id, "patch": &patch, "changes": changes }),
+        before,
+        audit::json(&user),
+    );
+    Ok(user)
 }
 
-// helper method to perform user insert.
-fn do_insert_user(user: &User) {
-    USER_STORAGE.with(|service| service.borrow_mut().insert(user.user_id, user.clone()));
+// helper method to perform user insert. stamps the change on the user and
+// keeps the user indexes in sync.
+fn do_insert_user(user: &mut User) {
+    user.updated_at = time();
+    user.updated_by = Some(ic_cdk::caller());
+    user.version += 1;
+    let previous = USER_STORAGE.with(|service| service.borrow_mut().insert(user.user_id, user.clone()));
+    user_index::update(previous.as_ref(), Some(user));
 }
 
+metered! {
 #[ic_cdk::update]
 fn delete_port(id: u64) -> Result<Port, Error> {
+    access::require_role(Role::PortAuthority)?;
+    proposal::require_direct(Operation::DeletePort)?;
+    remove_port("delete_port", id)
+}
+}
+
+// a helper method to remove a port along with everything tied to it. used in
+// delete_port and by approved delete proposals.
+fn remove_port(method: &str, id: u64) -> Result<Port, Error> {
+    if _get_port(&id).is_some() {
+        check_unreferenced(id)?;
+    }
     match PORT_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(port) => Ok(port),
+        Some(port) => {
+            port_index::update(Some(&port), None);
+            certification::update(id, None);
+            // ships can't wait for a port that no longer exists
+            anchorage::clear_port(id);
+            audit::record(method, EntityKind::Port, Some(id), json!({ "id": id }), audit::json(&port), None);
+            Ok(port)
+        }
         None => Err(Error::NotFound {
             msg: format!("couldn't delete a port with id={}. port not found.", id),
         }),
     }
 }
 
+metered! {
 #[ic_cdk::update]
 fn delete_user(user_id: u64) -> Result<User, Error> {
-    match USER_STORAGE.with(|service| service.borrow_mut().remove(&user_id)) {
-        Some(user) => Ok(user),
-        None => Err(Error::NotFound {
-            msg: format!("couldn't delete a user with id={}. user not found.", user_id),
+    let user = match _get_user(&user_id) {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't delete a user with id={}. user not found.", user_id),
+            })
+        }
+    };
+    access::require_owner_or_role(user.owner, Role::Admin)?;
+    remove_user("delete_user", user)
+}
+}
+
+// a helper method to remove a user and its index entries. used in delete_user
+// and delete_me. users whose principal still has port calls or reservations
+// in progress are kept, so those records don't lose their author.
+fn remove_user(method: &str, user: User) -> Result<User, Error> {
+    if let Some(owner) = user.owner {
+        if port_call::has_active_calls_by(&owner) || reservation::has_active_reservations_by(&owner) {
+            return Err(Error::Conflict {
+                msg: format!(
+                    "user with id={} still has active port calls or reservations; archive it instead",
+                    user.user_id
+                ),
+            });
+        }
+    }
+    USER_STORAGE.with(|service| service.borrow_mut().remove(&user.user_id));
+    user_index::update(Some(&user), None);
+    audit::record(
+        method,
+        EntityKind::User,
+        Some(user.user_id),
+        json!({ "user_id": user.user_id }),
+        audit::json(&user),
+        None,
+    );
+    Ok(user)
+}
+
+metered! {
+#[ic_cdk::update]
+fn archive_port(id: u64) -> Result<Port, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let mut port = match _get_port(&id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't archive a port with id={}. port not found", id),
+            })
+        }
+    };
+    check_open(&port)?;
+    let before = audit::json(&port);
+    port.archived_at = Some(time());
+    do_insert_port(&mut port);
+    // ships waiting at anchor would never be admitted
+    anchorage::clear_port(id);
+    audit::record("archive_port", EntityKind::Port, Some(id), json!({ "id": id }), before, audit::json(&port));
+    Ok(port)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn restore_port(id: u64) -> Result<Port, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let mut port = match _get_port(&id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't restore a port with id={}. port not found", id),
+            })
+        }
+    };
+    if port.archived_at.is_none() {
+        return Err(Error::Conflict {
+            msg: format!("port with id={} is not archived", id),
+        });
+    }
+    let before = audit::json(&port);
+    port.archived_at = None;
+    do_insert_port(&mut port);
+    audit::record("restore_port", EntityKind::Port, Some(id), json!({ "id": id }), before, audit::json(&port));
+    Ok(port)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn archive_user(user_id: u64) -> Result<User, Error> {
+    let mut user = match _get_user(&user_id) {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't archive a user with id={}. user not found", user_id),
+            })
+        }
+    };
+    access::require_owner_or_role(user.owner, Role::Admin)?;
+    if user.archived_at.is_some() {
+        return Err(Error::Conflict {
+            msg: format!("user with id={} is already archived", user_id),
+        });
+    }
+    let before = audit::json(&user);
+    user.archived_at = Some(time());
+    do_insert_user(&mut user);
+    audit::record(
+        "archive_user",
+        EntityKind::User,
+        Some(user_id),
+        json!({ "user_id": user_id }),
+        before,
+        audit::json(&user),
+    );
+    Ok(user)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn restore_user(user_id: u64) -> Result<User, Error> {
+    let mut user = match _get_user(&user_id) {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't restore a user with id={}. user not found", user_id),
+            })
+        }
+    };
+    access::require_owner_or_role(user.owner, Role::Admin)?;
+    if user.archived_at.is_none() {
+        return Err(Error::Conflict {
+            msg: format!("user with id={} is not archived", user_id),
+        });
+    }
+    let before = audit::json(&user);
+    user.archived_at = None;
+    do_insert_user(&mut user);
+    audit::record(
+        "restore_user",
+        EntityKind::User,
+        Some(user_id),
+        json!({ "user_id": user_id }),
+        before,
+        audit::json(&user),
+    );
+    Ok(user)
+}
+}
+
+// refuses a change based on a read of an older version of the record, so
+// concurrent edits don't silently overwrite each other.
+fn check_version(kind: &str, id: u64, version: u64, expected_version: u64) -> Result<(), Error> {
+    if version != expected_version {
+        return Err(Error::Conflict {
+            msg: format!(
+                "{} with id={} is at version {}, not {}; reload it and retry",
+                kind, id, version, expected_version
+            ),
+        });
+    }
+    Ok(())
+}
+
+// archived ports keep their history but take no new ships or bookings.
+fn check_open(port: &Port) -> Result<(), Error> {
+    match port.archived_at {
+        Some(_) => Err(Error::Conflict {
+            msg: format!("port with id={} is archived", port.id),
         }),
+        None => Ok(()),
+    }
+}
+
+// a port can only be removed for good while nothing refers to it; ports with
+// a history are archived instead.
+fn check_unreferenced(id: u64) -> Result<(), Error> {
+    let ships = ship::ship_ids_in_port(id).len();
+    if ships > 0 {
+        return Err(Error::Conflict {
+            msg: format!("port with id={} still has {} ships; archive it instead", id, ships),
+        });
+    }
+    if port_call::has_port_calls(id) {
+        return Err(Error::Conflict {
+            msg: format!("port with id={} is referenced by port calls; archive it instead", id),
+        });
+    }
+    if reservation::has_reservations(id) {
+        return Err(Error::Conflict {
+            msg: format!("port with id={} is referenced by reservations; archive it instead", id),
+        });
     }
+    Ok(())
 }
 
 // a helper method to get a port by id. used in get_port/update_port
@@ -220,112 +718,515 @@ fn _get_user(user_id: &u64) -> Option<User> {
     USER_STORAGE.with(|service| service.borrow().get(user_id))
 }
 
-#[ic_cdk::update]
-fn add_ship_to_port(port_id: u64) -> Result<(), Error> {
-    // Retrieve the port based on the given ID
-    match _get_port(&port_id) {
-        Some(mut port) => {
-            // Increment the current_ships count
-            port.current_ships += 1;
+// returns how many more ships the port can take, or an error if `arriving` ships
+// would overflow the counter.
+fn remaining_capacity(port: &Port, arriving: usize) -> Result<u32, Error> {
+    let arriving = u32::try_from(arriving).map_err(|_| Error::Overflow {
+        msg: format!("{} arriving ships exceed the supported range", arriving),
+    })?;
+    port.current_ships.checked_add(arriving).ok_or_else(|| Error::Overflow {
+        msg: format!("occupancy of port with id={} would overflow", port.id),
+    })?;
+    Ok(port.capacity.saturating_sub(port.current_ships))
+}
 
-            // Update the port in storage
-            do_insert_port(&port);
+metered! {
+#[ic_cdk::update]
+fn add_ship_to_port(port_id: u64, ship_id: u64) -> Result<Ship, Error> {
+    access::require_role(Role::Operator)?;
+    let before = ship::_get_ship(&ship_id);
+    let ship = admit_ship(port_id, ship_id)?;
+    audit::record(
+        "add_ship_to_port",
+        EntityKind::Ship,
+        Some(ship_id),
+        json!({ "port_id": port_id, "ship_id": ship_id }),
+        before.as_ref().and_then(audit::json),
+        audit::json(&ship),
+    );
+    Ok(ship)
+}
+}
 
-            Ok(())
+// a helper method to move a single ship into a port within its capacity.
+// used in add_ship_to_port and by port call arrivals.
+fn admit_ship(port_id: u64, ship_id: u64) -> Result<Ship, Error> {
+    // Retrieve the port based on the given ID
+    let port = match _get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't add a ship to port with id={}. port not found", port_id),
+            })
         }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't add a ship to port with id={}. port not found", port_id),
-        }),
+    };
+    check_open(&port)?;
+    let ship = match ship::_get_ship(&ship_id) {
+        Some(ship) => ship,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't add a ship with id={} to port. ship not found", ship_id),
+            })
+        }
+    };
+    if ship.current_port_id == Some(port_id) {
+        return Err(Error::InvalidInput {
+            msg: format!("ship with id={} is already in port with id={}", ship_id, port_id),
+            violations: Vec::new(),
+        });
     }
+
+    // Make sure there is room for one more ship
+    if remaining_capacity(&port, 1)? == 0 {
+        return Err(Error::CapacityExceeded {
+            msg: format!("port with id={} is full ({} ships)", port_id, port.capacity),
+        });
+    }
+
+    // Record the ship's new location; current_ships follows from it
+    Ok(ship::relocate_ship(ship, Some(port_id)))
 }
 
+// admits a batch of ships. `mode` defaults to AllOrNothing, which fails the
+// whole batch if it doesn't fit; ships only wait at anchor when the caller
+// opts in with AdmissionMode::Queue.
+metered! {
 #[ic_cdk::update]
-fn ships_arrival(port_id: u64, num_ships: u32) -> Result<(), Error> {
+fn ships_arrival(port_id: u64, ship_ids: Vec<u64>, mode: Option<AdmissionMode>) -> Result<ArrivalReport, Error> {
+    access::require_role(Role::Operator)?;
+    let mode = mode.unwrap_or_default();
+    let args = json!({ "port_id": port_id, "ship_ids": &ship_ids, "mode": mode });
     // Retrieve the port based on the given ID
-    match _get_port(&port_id) {
-        Some(mut port) => {
-            // Update the current_ships count based on the number of arriving ships
-            port.current_ships += num_ships;
-
-            // Update the port in storage
-            do_insert_port(&port);
+    let port = match _get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't handle ship arrival for port with id={}. port not found", port_id),
+            })
+        }
+    };
+    check_open(&port)?;
 
-            Ok(())
+    // Resolve every arriving ship before moving any of them
+    let mut ships: Vec<Ship> = Vec::with_capacity(ship_ids.len());
+    for ship_id in ship_ids {
+        let ship = match ship::_get_ship(&ship_id) {
+            Some(ship) => ship,
+            None => {
+                return Err(Error::NotFound {
+                    msg: format!("couldn't handle arrival of ship with id={}. ship not found", ship_id),
+                })
+            }
+        };
+        if ship.current_port_id == Some(port_id) || ships.iter().any(|other| other.id == ship_id) {
+            return Err(Error::InvalidInput {
+                msg: format!("ship with id={} is already in port with id={}", ship_id, port_id),
+                violations: Vec::new(),
+            });
         }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't handle ship arrival for port with id={}. port not found", port_id),
-        }),
+        ships.push(ship);
     }
+
+    let admitted = admissible(&port, ships.len(), mode)?;
+
+    // Admit up to the remaining capacity; whoever didn't fit waits at anchor
+    // or is reported back
+    let mut report = ArrivalReport::default();
+    let waiting = ships.split_off(admitted);
+    report.admitted = ships
+        .into_iter()
+        .map(|ship| ship::relocate_ship(ship, Some(port_id)).id)
+        .collect();
+    turn_away(port_id, waiting, mode, time(), &mut report);
+    audit::record(
+        "ships_arrival",
+        EntityKind::Port,
+        Some(port_id),
+        args,
+        audit::json(&port),
+        _get_port(&port_id).as_ref().and_then(audit::json),
+    );
+    Ok(report)
+}
 }
 
-#[ic_cdk::query]
-fn get_all_ports() -> Vec<Port> {
-    PORT_STORAGE.with(|service| service.borrow().iter().map(|(_, port)| port.clone()).collect())
+// queues the ships that didn't fit in Queue mode and reports the rest as
+// rejected. as in enqueue_ship, only ships outside any port can wait at anchor.
+fn turn_away(port_id: u64, waiting: Vec<Ship>, mode: AdmissionMode, now: u64, report: &mut ArrivalReport) {
+    for ship in waiting {
+        if mode == AdmissionMode::Queue && ship.current_port_id.is_none() {
+            anchorage::remove_ship(ship.id);
+            report.queued.push(anchorage::push(port_id, &ship, PriorityClass::for_ship(&ship), now).ship_id);
+        } else {
+            report.rejected.push(ship.id);
+        }
+    }
 }
 
-#[ic_cdk::query]
-fn get_all_users() -> Vec<User> {
-    USER_STORAGE.with(|service| service.borrow().iter().map(|(_, user)| user.clone()).collect())
+// the caller of a query and its role.
+fn viewer() -> (Principal, Option<Role>) {
+    let caller = ic_cdk::caller();
+    (caller, access::role_of(&caller))
 }
 
-// Admin-related functionality
-#[ic_cdk::query]
-fn get_admin() -> u64 {
-    // Return the hard-coded admin ID
-    1
+// a user as `viewer` may see it. the user's owner and admins see the whole
+// record; anyone else gets it without the email and the principals, which
+// would tie the user to the principal it signs in as.
+fn visible_user(mut user: User, viewer: &Principal, role: Option<Role>) -> User {
+    let is_owner = *viewer != Principal::anonymous() && user.owner == Some(*viewer);
+    if !is_owner && role != Some(Role::Admin) {
+        user.email = String::new();
+        user.owner = None;
+        user.updated_by = None;
+    }
+    user
 }
 
-#[ic_cdk::update]
-fn transfer_ships_admin(source_port_id: u64, destination_port_id: u64, num_ships: u32, admin_id: u64) -> Result<(), Error> {
-    // Check if the caller is an admin
-    if admin_id != get_admin() {
-        return Err(Error::Unauthorized {
-            msg: "only admin can transfer ships".to_string(),
+// how many of `arriving` ships the port takes in `mode`. AllOrNothing takes
+// all of them or fails with CapacityExceeded.
+fn admissible(port: &Port, arriving: usize, mode: AdmissionMode) -> Result<usize, Error> {
+    let remaining = remaining_capacity(port, arriving)? as usize;
+    if arriving > remaining && mode == AdmissionMode::AllOrNothing {
+        return Err(Error::CapacityExceeded {
+            msg: format!(
+                "port with id={} has room for {} ships, {} arriving",
+                port.id, remaining, arriving
+            ),
         });
     }
-    // Transfer ships
-    let mut source_port = match _get_port(&source_port_id) {
+    Ok(arriving.min(remaining))
+}
+
+metered! {
+#[ic_cdk::update]
+fn ship_departure(port_id: u64, ship_id: u64) -> Result<Ship, Error> {
+    access::require_role(Role::Operator)?;
+    port_call::check_no_open_call(port_id, ship_id)?;
+    let before = ship::_get_ship(&ship_id);
+    let ship = release_ship(port_id, ship_id)?;
+    audit::record(
+        "ship_departure",
+        EntityKind::Ship,
+        Some(ship_id),
+        json!({ "port_id": port_id, "ship_id": ship_id }),
+        before.as_ref().and_then(audit::json),
+        audit::json(&ship),
+    );
+    Ok(ship)
+}
+}
+
+// a helper method to take a single ship out of a port.
+// used in ship_departure and by port call departures.
+fn release_ship(port_id: u64, ship_id: u64) -> Result<Ship, Error> {
+    // Retrieve the port based on the given ID
+    let port = match _get_port(&port_id) {
         Some(port) => port,
         None => {
             return Err(Error::NotFound {
-                msg: format!("source port with id={} not found", source_port_id),
+                msg: format!("couldn't remove a ship from port with id={}. port not found", port_id),
             })
         }
     };
-
-    // Check if there are enough ships in the source port
-    if source_port.current_ships < num_ships {
-        return Err(Error::NotFound {
-            msg: format!("insufficient ships in source port with id={}", source_port_id),
+    let ship = match ship::_get_ship(&ship_id) {
+        Some(ship) => ship,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't handle departure of ship with id={}. ship not found", ship_id),
+            })
+        }
+    };
+    if ship.current_port_id != Some(port_id) {
+        return Err(Error::InvalidInput {
+            msg: format!("ship with id={} is not in port with id={}", ship_id, port_id),
+            violations: Vec::new(),
         });
     }
+    check_departures(&port, 1)?;
 
-    // Retrieve destination port
-    let mut destination_port = match _get_port(&destination_port_id) {
+    // Clear the ship's location; current_ships follows from it
+    Ok(ship::relocate_ship(ship, None))
+}
+
+metered! {
+#[ic_cdk::update]
+fn ships_departure(port_id: u64, ship_ids: Vec<u64>) -> Result<Vec<u64>, Error> {
+    access::require_role(Role::Operator)?;
+    let args = json!({ "port_id": port_id, "ship_ids": &ship_ids });
+    // Retrieve the port based on the given ID
+    let port = match _get_port(&port_id) {
         Some(port) => port,
         None => {
             return Err(Error::NotFound {
-                msg: format!("destination port with id={} not found", destination_port_id),
+                msg: format!("couldn't handle ship departure for port with id={}. port not found", port_id),
             })
         }
     };
 
-    // Transfer ships
-    source_port.current_ships -= num_ships;
-    destination_port.current_ships += num_ships;
+    // Every departing ship must currently be in the port
+    let mut ships: Vec<Ship> = Vec::with_capacity(ship_ids.len());
+    for ship_id in ship_ids {
+        let ship = match ship::_get_ship(&ship_id) {
+            Some(ship) => ship,
+            None => {
+                return Err(Error::NotFound {
+                    msg: format!("couldn't handle departure of ship with id={}. ship not found", ship_id),
+                })
+            }
+        };
+        if ship.current_port_id != Some(port_id) || ships.iter().any(|other| other.id == ship_id) {
+            return Err(Error::InvalidInput {
+                msg: format!("ship with id={} is not in port with id={}", ship_id, port_id),
+                violations: Vec::new(),
+            });
+        }
+        port_call::check_no_open_call(port_id, ship_id)?;
+        ships.push(ship);
+    }
+    check_departures(&port, ships.len())?;
+
+    let departed = ships
+        .into_iter()
+        .map(|ship| ship::relocate_ship(ship, None).id)
+        .collect();
+    audit::record(
+        "ships_departure",
+        EntityKind::Port,
+        Some(port_id),
+        args,
+        audit::json(&port),
+        _get_port(&port_id).as_ref().and_then(audit::json),
+    );
+    Ok(departed)
+}
+}
+
+// makes sure `departing` ships can leave without the occupancy going below zero.
+fn check_departures(port: &Port, departing: usize) -> Result<(), Error> {
+    u32::try_from(departing)
+        .ok()
+        .and_then(|departing| port.current_ships.checked_sub(departing))
+        .map(|_| ())
+        .ok_or_else(|| Error::Overflow {
+            msg: format!(
+                "{} departures would take port with id={} below zero ships",
+                departing, port.id
+            ),
+        })
+}
 
-    // Update ports in storage
-    do_insert_port(&source_port);
-    do_insert_port(&destination_port);
+#[ic_cdk::query]
+fn get_all_ports() -> Vec<Port> {
+    PORT_STORAGE.with(|service| service.borrow().iter().map(|(_, port)| port.clone()).collect())
+}
 
-    Ok(())
+#[ic_cdk::query]
+fn get_all_users() -> Vec<User> {
+    let (viewer, role) = viewer();
+    USER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, user)| visible_user(user, &viewer, role))
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn list_ports(cursor: Option<Vec<u8>>, limit: u64, sort: Option<PortSort>) -> Result<PortPage, Error> {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    port_index::page(sort.unwrap_or_default(), cursor.as_deref(), limit)
+}
+
+#[ic_cdk::query]
+fn search_ports(filter: PortSearch, cursor: Option<Vec<u8>>, limit: u64) -> Result<PortPage, Error> {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    port_index::search(&filter, cursor.as_deref(), limit)
 }
 
-#[derive(candid::CandidType, Deserialize, Serialize)]
+#[ic_cdk::query]
+fn list_users(cursor: Option<u64>, limit: u64) -> UserPage {
+    let (viewer, role) = viewer();
+    let mut page = users_page(cursor, limit);
+    page.users = page
+        .users
+        .into_iter()
+        .map(|user| visible_user(user, &viewer, role))
+        .collect();
+    page
+}
+
+// a page of users as stored; callers outside the canister get them through
+// visible_user.
+pub(crate) fn users_page(cursor: Option<u64>, limit: u64) -> UserPage {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    USER_STORAGE.with(|service| {
+        let service = service.borrow();
+        let mut users: Vec<User> = service
+            .range(cursor.unwrap_or(0)..)
+            .take(limit + 1)
+            .map(|(_, user)| user)
+            .collect();
+        let next_cursor = (users.len() > limit).then(|| users.remove(limit).user_id);
+        UserPage {
+            users,
+            next_cursor,
+            total: service.len(),
+        }
+    })
+}
+
+#[derive(candid::CandidType, Clone, Debug, Deserialize, Serialize)]
 enum Error {
     NotFound { msg: String },
     Unauthorized { msg: String },
+    CapacityExceeded { msg: String },
+    // `violations` lists the offending fields when a payload fails validation
+    InvalidInput { msg: String, violations: Vec<FieldViolation> },
+    Overflow { msg: String },
+    Conflict { msg: String },
 }
 
 // need this to generate candid
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(id: u64, capacity: u32, current_ships: u32) -> Port {
+        Port {
+            id,
+            name: format!("port {}", id),
+            location: "Rotterdam".to_string(),
+            capacity,
+            current_ships,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn remaining_capacity_is_zero_for_full_and_overfull_ports() {
+        assert_eq!(remaining_capacity(&port(1, 10, 0), 1).ok(), Some(10));
+        assert_eq!(remaining_capacity(&port(1, 10, 9), 5).ok(), Some(1));
+        assert_eq!(remaining_capacity(&port(1, 10, 10), 1).ok(), Some(0));
+        // capacity lowered below the ships already in port
+        assert_eq!(remaining_capacity(&port(1, 10, 12), 1).ok(), Some(0));
+        assert_eq!(remaining_capacity(&port(1, 0, 0), 0).ok(), Some(0));
+    }
+
+    #[test]
+    fn occupancy_that_would_overflow_is_an_error() {
+        let crowded = port(1, u32::MAX, u32::MAX - 1);
+        assert_eq!(remaining_capacity(&crowded, 1).ok(), Some(1));
+        assert!(matches!(remaining_capacity(&crowded, 2), Err(Error::Overflow { .. })));
+        assert!(matches!(
+            remaining_capacity(&port(1, 10, 0), u32::MAX as usize + 1),
+            Err(Error::Overflow { .. })
+        ));
+        assert!(matches!(check_departures(&port(1, 10, 1), 2), Err(Error::Overflow { .. })));
+        assert!(check_departures(&port(1, 10, 2), 2).is_ok());
+    }
+
+    #[test]
+    fn only_owners_and_admins_see_emails_and_principals() {
+        let owner = Principal::from_slice(&[1; 29]);
+        let stranger = Principal::from_slice(&[2; 29]);
+        let user = User {
+            user_id: 1,
+            username: "ada".to_string(),
+            email: "ada@example.com".to_string(),
+            owner: Some(owner),
+            updated_by: Some(owner),
+            ..Default::default()
+        };
+        let sees_email = |viewer: &Principal, role| !visible_user(user.clone(), viewer, role).email.is_empty();
+
+        assert!(sees_email(&owner, None));
+        assert!(sees_email(&stranger, Some(Role::Admin)));
+        assert!(!sees_email(&stranger, Some(Role::PortAuthority)));
+        assert!(!sees_email(&Principal::anonymous(), None));
+        let redacted = visible_user(user.clone(), &stranger, None);
+        assert_eq!((redacted.owner, redacted.updated_by, redacted.username), (None, None, "ada".to_string()));
+
+        // a user without an owner isn't anonymous's to read
+        let unowned = User { owner: None, ..user };
+        assert!(visible_user(unowned, &Principal::anonymous(), None).email.is_empty());
+    }
+
+    #[test]
+    fn only_queue_mode_puts_ships_at_anchor() {
+        let docked = Ship {
+            id: 11,
+            current_port_id: Some(2),
+            ..Default::default()
+        };
+        let waiting = || vec![Ship { id: 10, ..Default::default() }, docked.clone()];
+
+        let mut report = ArrivalReport::default();
+        turn_away(1, waiting(), AdmissionMode::Queue, 0, &mut report);
+        assert_eq!((report.queued, report.rejected), (vec![10], vec![11]));
+        assert_eq!(anchorage::port_queue(1).len(), 1);
+
+        let mut report = ArrivalReport::default();
+        turn_away(3, waiting(), AdmissionMode::Partial, 0, &mut report);
+        assert_eq!((report.queued, report.rejected), (vec![], vec![10, 11]));
+        assert!(anchorage::port_queue(3).is_empty());
+    }
+
+    #[test]
+    fn arrivals_are_all_or_nothing_by_default() {
+        let full = port(1, 10, 9);
+        assert!(matches!(
+            admissible(&full, 2, AdmissionMode::default()),
+            Err(Error::CapacityExceeded { .. })
+        ));
+        assert_eq!(admissible(&full, 1, AdmissionMode::default()).ok(), Some(1));
+    }
+
+    #[test]
+    fn arrivals_are_admitted_up_to_the_remaining_capacity() {
+        let partly_full = port(1, 10, 8);
+        for mode in [AdmissionMode::AllOrNothing, AdmissionMode::Partial, AdmissionMode::Queue] {
+            assert_eq!(admissible(&partly_full, 0, mode).ok(), Some(0));
+            assert_eq!(admissible(&partly_full, 2, mode).ok(), Some(2));
+        }
+        assert!(matches!(
+            admissible(&partly_full, 3, AdmissionMode::AllOrNothing),
+            Err(Error::CapacityExceeded { .. })
+        ));
+        assert_eq!(admissible(&partly_full, 3, AdmissionMode::Partial).ok(), Some(2));
+        assert_eq!(admissible(&partly_full, 3, AdmissionMode::Queue).ok(), Some(2));
+
+        let overfull = port(2, 10, 11);
+        assert!(matches!(
+            admissible(&overfull, 1, AdmissionMode::AllOrNothing),
+            Err(Error::CapacityExceeded { .. })
+        ));
+        assert_eq!(admissible(&overfull, 4, AdmissionMode::Partial).ok(), Some(0));
+    }
+
+    #[test]
+    fn single_arrivals_need_an_open_port_with_room() {
+        PORT_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            service.insert(1, port(1, 1, 1));
+            service.insert(2, Port {
+                archived_at: Some(1),
+                ..port(2, 10, 0)
+            });
+        });
+        ship::SHIP_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            service.insert(10, Ship { id: 10, current_port_id: Some(1), ..Default::default() });
+            service.insert(11, Ship { id: 11, ..Default::default() });
+        });
+
+        assert!(matches!(admit_ship(1, 11), Err(Error::CapacityExceeded { .. })));
+        assert!(matches!(admit_ship(1, 10), Err(Error::InvalidInput { .. })));
+        assert!(matches!(admit_ship(2, 11), Err(Error::Conflict { .. })));
+        assert!(matches!(admit_ship(3, 11), Err(Error::NotFound { .. })));
+        assert!(matches!(admit_ship(2, 12), Err(Error::Conflict { .. })));
+        assert!(matches!(admit_ship(1, 12), Err(Error::NotFound { .. })));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::access::{self, Role};
+use crate::ship;
+use crate::{Error, Memory, MEMORY_MANAGER, PORT_STORAGE, USER_STORAGE};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{Memory as _, StableBTreeMap};
+use std::cell::RefCell;
+use std::fmt::Write;
+
+// Operational metrics. Domain counts and memory figures are read when asked
+// for; call and error counts are kept per update method. Queries can't persist
+// anything, so they aren't counted, and both outputs say so. get_metrics is for
+// operators; the public /metrics page leaves out cycles and memory.
+
+const WASM_PAGE_SIZE: u64 = 65536;
+// what a successful call is counted under
+const OK: &str = "Ok";
+
+// (method name, "Ok" or the Error variant)
+type OutcomeKey = (Blob<64>, Blob<16>);
+
+thread_local! {
+    static CALL_COUNTS: RefCell<StableBTreeMap<OutcomeKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+    ));
+}
+
+// Records, ships and how full the ports are.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Counts {
+    pub ports: u64,
+    pub archived_ports: u64,
+    pub users: u64,
+    pub ships: u64,
+    pub ships_in_port: u64,
+    pub total_capacity: u64,
+}
+
+impl Counts {
+    // ships in port over total capacity; 0 without any capacity.
+    pub(crate) fn occupancy(&self) -> f64 {
+        match self.total_capacity {
+            0 => 0.0,
+            capacity => self.ships_in_port as f64 / capacity as f64,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ErrorCount {
+    variant: String,
+    count: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MethodMetrics {
+    method: String,
+    // every completed call, failed ones included
+    calls: u64,
+    errors: Vec<ErrorCount>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MemoryUsage {
+    memory_id: u8,
+    bytes: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Metrics {
+    timestamp: u64,
+    counts: Counts,
+    // calls of update methods; query calls aren't counted
+    update_methods: Vec<MethodMetrics>,
+    // stable memory by MemoryId, for the ids in use
+    memory: Vec<MemoryUsage>,
+    stable_memory_bytes: u64,
+    heap_bytes: u64,
+    cycles: u128,
+}
+
+#[ic_cdk::query]
+fn get_metrics() -> Result<Metrics, Error> {
+    access::require_role(Role::Operator)?;
+    Ok(Metrics {
+        timestamp: time(),
+        counts: counts(),
+        update_methods: methods(),
+        memory: memory(),
+        stable_memory_bytes: ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE,
+        heap_bytes: heap_bytes(),
+        cycles: ic_cdk::api::canister_balance128(),
+    })
+}
+
+// Wraps an update method so every completed call is counted under its name
+// and outcome. The body is left as written; `return` and `?` end the call as
+// usual.
+macro_rules! metered {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block) => {
+        $(#[$attr])*
+        $vis fn $name($($arg: $ty),*) -> $ret {
+            $crate::metrics::observe(stringify!($name), move || $body)
+        }
+    };
+    ($(#[$attr:meta])* $vis:vis async fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block) => {
+        $(#[$attr])*
+        $vis async fn $name($($arg: $ty),*) -> $ret {
+            let result: $ret = async move $body.await;
+            $crate::metrics::record(stringify!($name), &result);
+            result
+        }
+    };
+}
+pub(crate) use metered;
+
+// runs an update method's body and counts how it ended.
+pub(crate) fn observe<T>(method: &str, call: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let result = call();
+    record(method, &result);
+    result
+}
+
+// counts a completed call of an update method.
+pub(crate) fn record<T>(method: &str, result: &Result<T, Error>) {
+    let outcome = match result {
+        Ok(_) => OK,
+        Err(error) => error.variant(),
+    };
+    let key = (
+        Blob::try_from(method.as_bytes()).expect("method name within bounds"),
+        Blob::try_from(outcome.as_bytes()).expect("variant name within bounds"),
+    );
+    CALL_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let count = counts.get(&key).unwrap_or(0);
+        counts.insert(key, count + 1);
+    });
+}
+
+pub(crate) fn counts() -> Counts {
+    let mut counts = PORT_STORAGE.with(|service| {
+        service.borrow().iter().fold(Counts::default(), |mut counts, (_, port)| {
+            counts.ports += 1;
+            counts.archived_ports += port.archived_at.is_some() as u64;
+            counts.ships_in_port += port.current_ships as u64;
+            counts.total_capacity += port.capacity as u64;
+            counts
+        })
+    });
+    counts.users = USER_STORAGE.with(|service| service.borrow().len());
+    counts.ships = ship::ship_count();
+    counts
+}
+
+// the public metrics in the Prometheus text exposition format: domain counts
+// and update call counts, without the canister's cycles and memory.
+pub(crate) fn prometheus() -> String {
+    render(&counts(), &methods())
+}
+
+fn render(counts: &Counts, methods: &[MethodMetrics]) -> String {
+    let mut text = String::new();
+    text.push_str("# Query calls aren't counted. Cycles and memory are reported to operators by get_metrics.\n");
+    let mut gauge = |name: &str, help: &str, value: String| {
+        let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+    };
+    gauge("ports", "Ports, archived ones included.", counts.ports.to_string());
+    gauge("ports_archived", "Archived ports.", counts.archived_ports.to_string());
+    gauge("users", "Registered users, archived ones included.", counts.users.to_string());
+    gauge("ships", "Registered ships.", counts.ships.to_string());
+    gauge("ships_in_port", "Ships currently in a port.", counts.ships_in_port.to_string());
+    gauge("port_capacity", "Total capacity of all ports.", counts.total_capacity.to_string());
+    gauge(
+        "port_occupancy_ratio",
+        "Ships in port over total capacity.",
+        counts.occupancy().to_string(),
+    );
+
+    text.push_str("# HELP method_calls_total Completed calls of each update method. Queries aren't counted.\n");
+    text.push_str("# TYPE method_calls_total counter\n");
+    for method in methods {
+        let _ = writeln!(text, "method_calls_total{{method=\"{}\"}} {}", method.method, method.calls);
+    }
+    text.push_str("# HELP method_errors_total Update calls that returned an error, by Error variant.\n");
+    text.push_str("# TYPE method_errors_total counter\n");
+    for method in methods {
+        for error in &method.errors {
+            let _ = writeln!(
+                text,
+                "method_errors_total{{method=\"{}\",error=\"{}\"}} {}",
+                method.method, error.variant, error.count
+            );
+        }
+    }
+    text
+}
+
+// call and error counts per method, in method name order.
+fn methods() -> Vec<MethodMetrics> {
+    let mut methods: Vec<MethodMetrics> = Vec::new();
+    CALL_COUNTS.with(|counts| {
+        for ((method, outcome), count) in counts.borrow().iter() {
+            let method = String::from_utf8_lossy(method.as_slice()).into_owned();
+            let outcome = String::from_utf8_lossy(outcome.as_slice()).into_owned();
+            if methods.last().is_none_or(|last| last.method != method) {
+                methods.push(MethodMetrics {
+                    method,
+                    calls: 0,
+                    errors: Vec::new(),
+                });
+            }
+            let entry = methods.last_mut().expect("pushed above");
+            entry.calls += count;
+            if outcome != OK {
+                entry.errors.push(ErrorCount { variant: outcome, count });
+            }
+        }
+    });
+    methods
+}
+
+fn memory() -> Vec<MemoryUsage> {
+    MEMORY_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        // 255 marks unallocated buckets and is not a valid id
+        (0..u8::MAX)
+            .map(|id| MemoryUsage {
+                memory_id: id,
+                bytes: manager.get(MemoryId::new(id)).size() * WASM_PAGE_SIZE,
+            })
+            .filter(|usage| usage.bytes > 0)
+            .collect()
+    })
+}
+
+fn heap_bytes() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+impl Error {
+    // the variant's name, as metrics report it.
+    fn variant(&self) -> &'static str {
+        match self {
+            Error::NotFound { .. } => "NotFound",
+            Error::Unauthorized { .. } => "Unauthorized",
+            Error::CapacityExceeded { .. } => "CapacityExceeded",
+            Error::InvalidInput { .. } => "InvalidInput",
+            Error::Overflow { .. } => "Overflow",
+            Error::Conflict { .. } => "Conflict",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found() -> Error {
+        Error::NotFound { msg: String::new() }
+    }
+
+    #[test]
+    fn calls_are_grouped_by_method_with_errors_by_variant() {
+        record("delete_port", &Ok(()));
+        record::<()>("add_port", &Err(not_found()));
+        record("add_port", &Ok(()));
+        record::<()>("add_port", &Err(Error::Conflict { msg: String::new() }));
+        record::<()>("add_port", &Err(not_found()));
+        let _ = observe("add_port_call", || Ok(()));
+
+        let methods = methods();
+        let names: Vec<&str> = methods.iter().map(|method| method.method.as_str()).collect();
+        assert_eq!(names, vec!["add_port", "add_port_call", "delete_port"]);
+        let calls: Vec<u64> = methods.iter().map(|method| method.calls).collect();
+        assert_eq!(calls, vec![4, 1, 1]);
+        let errors: Vec<(&str, u64)> = methods[0]
+            .errors
+            .iter()
+            .map(|error| (error.variant.as_str(), error.count))
+            .collect();
+        assert_eq!(errors, vec![("Conflict", 1), ("NotFound", 2)]);
+        assert!(methods[1].errors.is_empty() && methods[2].errors.is_empty());
+    }
+
+    #[test]
+    fn prometheus_text_has_typed_families_and_labelled_samples() {
+        let counts = Counts {
+            ports: 2,
+            archived_ports: 1,
+            users: 3,
+            ships: 5,
+            ships_in_port: 3,
+            total_capacity: 4,
+        };
+        let methods = [MethodMetrics {
+            method: "add_port".to_string(),
+            calls: 3,
+            errors: vec![ErrorCount {
+                variant: "NotFound".to_string(),
+                count: 1,
+            }],
+        }];
+        let text = render(&counts, &methods);
+        let lines: Vec<&str> = text.lines().collect();
+
+        for expected in [
+            "# HELP ports Ports, archived ones included.",
+            "# TYPE ports gauge",
+            "ports 2",
+            "ports_archived 1",
+            "users 3",
+            "ships 5",
+            "ships_in_port 3",
+            "port_capacity 4",
+            "port_occupancy_ratio 0.75",
+            "# TYPE method_calls_total counter",
+            "method_calls_total{method=\"add_port\"} 3",
+            "# TYPE method_errors_total counter",
+            "method_errors_total{method=\"add_port\",error=\"NotFound\"} 1",
+        ] {
+            assert!(lines.contains(&expected), "missing line {:?} in\n{}", expected, text);
+        }
+        // every sample belongs to a family declared just before it
+        let mut declared = Vec::new();
+        for line in &lines {
+            match line.strip_prefix("# TYPE ") {
+                Some(family) => declared.push(family.split(' ').next().unwrap()),
+                None if line.starts_with('#') => (),
+                None => {
+                    let name = line.split(['{', ' ']).next().unwrap();
+                    assert_eq!(declared.last(), Some(&name), "sample {:?} outside its family", line);
+                }
+            }
+        }
+        assert!(text.ends_with('\n'));
+        // anyone can read /metrics
+        assert!(!text.contains("canister_"));
+        assert!(lines[0].contains("Query calls aren't counted"));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::sequence::{self, Sequence};
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum MovementKind {
+    Arrival,
+    Departure,
+}
+
+// A ship entering or leaving a port. `occupancy_after` is the port's current_ships
+// right after the movement, so occupancy at any point in time can be read back
+// from the latest event before it.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MovementEvent {
+    pub id: u64,
+    pub port_id: u64,
+    pub ship_id: u64,
+    pub kind: MovementKind,
+    pub timestamp: u64,
+    pub occupancy_after: u32,
+}
+
+impl Storable for MovementEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MovementEvent {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // keyed by (port_id, event_id); event ids grow with time so each port's
+    // events are stored in chronological order.
+    static MOVEMENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), MovementEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+    ));
+}
+
+// appends a movement event for the given port.
+pub(crate) fn record(port_id: u64, ship_id: u64, kind: MovementKind, occupancy_after: u32) {
+    let event = MovementEvent {
+        id: sequence::next(Sequence::Movement),
+        port_id,
+        ship_id,
+        kind,
+        timestamp: time(),
+        occupancy_after,
+    };
+    MOVEMENT_STORAGE.with(|service| service.borrow_mut().insert((port_id, event.id), event));
+}
+
+// a helper method to collect a port's events within [from, to], oldest first.
+// events are stored in chronological order, so the scan stops past `to`.
+fn port_events(port_id: u64, from: Option<u64>, to: Option<u64>) -> Vec<MovementEvent> {
+    let from = from.unwrap_or(0);
+    let to = to.unwrap_or(u64::MAX);
+    MOVEMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((port_id, 0)..=(port_id, u64::MAX))
+            .map(|(_, event)| event)
+            .skip_while(|event| event.timestamp < from)
+            .take_while(|event| event.timestamp <= to)
+            .collect()
+    })
+}
+
+// the port's occupancy right after its latest event at or before `timestamp`,
+// found by stepping back from its newest event, so only events after
+// `timestamp` are read.
+fn occupancy_at(port_id: u64, timestamp: u64) -> u32 {
+    MOVEMENT_STORAGE.with(|service| {
+        let service = service.borrow();
+        // event ids are sequence values, so (port_id, u64::MAX) is past the
+        // port's newest event
+        let mut bound = (port_id, u64::MAX);
+        while let Some((key, event)) = service.iter_upper_bound(&bound).next() {
+            if key.0 != port_id {
+                break;
+            }
+            if event.timestamp <= timestamp {
+                return event.occupancy_after;
+            }
+            bound = key;
+        }
+        0
+    })
+}
+
+// mean time between a ship's arrival and its departure over the stays
+// recorded for the port, or None if no ship has left it yet.
+pub(crate) fn average_dwell(port_id: u64) -> Option<u64> {
+    let mut arrivals: std::collections::BTreeMap<u64, u64> = std::collections::BTreeMap::new();
+    let (mut total, mut stays) = (0u128, 0u128);
+    for event in port_events(port_id, None, None) {
+        match event.kind {
+            MovementKind::Arrival => {
+                arrivals.insert(event.ship_id, event.timestamp);
+            }
+            MovementKind::Departure => {
+                if let Some(arrived_at) = arrivals.remove(&event.ship_id) {
+                    total += event.timestamp.saturating_sub(arrived_at) as u128;
+                    stays += 1;
+                }
+            }
+        }
+    }
+    (stays > 0).then(|| (total / stays) as u64)
+}
+
+#[ic_cdk::query]
+fn get_port_movements(port_id: u64, from: Option<u64>, to: Option<u64>) -> Result<Vec<MovementEvent>, Error> {
+    if crate::_get_port(&port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a port with id={} not found", port_id),
+        });
+    }
+    Ok(port_events(port_id, from, to))
+}
+
+#[ic_cdk::query]
+fn get_port_occupancy_at(port_id: u64, timestamp: u64) -> Result<u32, Error> {
+    if crate::_get_port(&port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a port with id={} not found", port_id),
+        });
+    }
+    Ok(occupancy_at(port_id, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // stores an event at the given time; record itself reads the clock
+    fn event(port_id: u64, ship_id: u64, kind: MovementKind, timestamp: u64, occupancy_after: u32) {
+        let id = sequence::next(Sequence::Movement);
+        let event = MovementEvent {
+            id,
+            port_id,
+            ship_id,
+            kind,
+            timestamp,
+            occupancy_after,
+        };
+        MOVEMENT_STORAGE.with(|service| service.borrow_mut().insert((port_id, id), event));
+    }
+
+    fn setup() {
+        event(1, 10, MovementKind::Arrival, 100, 1);
+        event(2, 20, MovementKind::Arrival, 150, 1);
+        event(1, 11, MovementKind::Arrival, 200, 2);
+        event(1, 10, MovementKind::Departure, 300, 1);
+        event(3, 30, MovementKind::Arrival, 350, 1);
+    }
+
+    fn ships(events: Vec<MovementEvent>) -> Vec<(u64, u64)> {
+        events.into_iter().map(|event| (event.ship_id, event.timestamp)).collect()
+    }
+
+    #[test]
+    fn a_ports_events_come_oldest_first() {
+        setup();
+        assert_eq!(ships(port_events(1, None, None)), [(10, 100), (11, 200), (10, 300)]);
+        assert_eq!(ships(port_events(2, None, None)), [(20, 150)]);
+    }
+
+    #[test]
+    fn range_filters_are_inclusive() {
+        setup();
+        assert_eq!(ships(port_events(1, Some(200), None)), [(11, 200), (10, 300)]);
+        assert_eq!(ships(port_events(1, None, Some(200))), [(10, 100), (11, 200)]);
+        assert_eq!(ships(port_events(1, Some(101), Some(299))), [(11, 200)]);
+        assert!(port_events(1, Some(301), None).is_empty());
+    }
+
+    #[test]
+    fn occupancy_is_read_from_the_latest_event_before() {
+        setup();
+        let at = |timestamp| occupancy_at(1, timestamp);
+        assert_eq!((at(99), at(100), at(199), at(200), at(300), at(u64::MAX)), (0, 1, 1, 2, 1, 1));
+        // neighbouring ports don't leak into each other
+        assert_eq!((occupancy_at(2, 149), occupancy_at(2, 400), occupancy_at(4, 400)), (0, 1, 0));
+    }
+
+    #[test]
+    fn dwell_averages_completed_stays() {
+        setup();
+        assert_eq!(average_dwell(1), Some(200));
+        assert_eq!(average_dwell(2), None);
+    }
+}
@@ -0,0 +1,484 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::berth::{self, Berth, BerthStatus};
+use crate::metrics::metered;
+use crate::port_call::{self, PortCallStatus};
+use crate::reservation::{self, BerthReservation, ReservationPayload};
+use crate::ship::{self, Ship};
+use crate::Error;
+use serde_json::json;
+
+// Up to this many calls every processing order is tried; larger sets only get
+// the greedy orders.
+const ALL_ORDERS_LIMIT: usize = 6;
+// Calls one plan may cover, to stay within the instruction limit.
+const MAX_PLAN_SIZE: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PlanRequest {
+    port_call_id: u64,
+    // higher is more urgent; waiting time is weighted by priority + 1
+    priority: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PlannedBerthing {
+    port_call_id: u64,
+    ship_id: u64,
+    berth_id: u64,
+    start: u64,
+    end: u64,
+    waiting: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BerthPlan {
+    port_id: u64,
+    assignments: Vec<PlannedBerthing>,
+    // calls no berth of the port can take
+    unassigned: Vec<u64>,
+    total_waiting: u64,
+    // whether every processing order was tried. each order is still placed
+    // greedily, so even then the plan isn't guaranteed to be optimal
+    all_orders_tried: bool,
+}
+
+// a port call reduced to what the planner needs.
+struct Job {
+    port_call_id: u64,
+    ship: Ship,
+    eta: u64,
+    dwell: u64,
+    weight: u128,
+}
+
+// a berth and its busy intervals, sorted by start.
+#[derive(Clone)]
+struct Lane {
+    berth: Berth,
+    busy: Vec<(u64, u64)>,
+}
+
+impl Lane {
+    // earliest start >= eta at which `dwell` fits between the busy intervals.
+    fn earliest_start(&self, eta: u64, dwell: u64) -> u64 {
+        let mut start = eta;
+        for &(busy_start, busy_end) in &self.busy {
+            if start.saturating_add(dwell) <= busy_start {
+                break;
+            }
+            start = start.max(busy_end);
+        }
+        start
+    }
+
+    fn book(&mut self, start: u64, end: u64) {
+        let position = self.busy.partition_point(|&(busy_start, _)| busy_start < start);
+        self.busy.insert(position, (start, end));
+    }
+}
+
+#[ic_cdk::query]
+fn plan_berths(port_id: u64, requests: Vec<PlanRequest>) -> Result<BerthPlan, Error> {
+    access::require_role(Role::Operator)?;
+    check_plan_size(requests.len())?;
+    let jobs = load_jobs(port_id, requests)?;
+    let lanes = load_lanes(port_id);
+    Ok(best_plan(port_id, &jobs, &lanes))
+}
+
+// the best of the greedy orders, and of every order for small instances.
+fn best_plan(port_id: u64, jobs: &[Job], lanes: &[Lane]) -> BerthPlan {
+    let mut orders: Vec<Vec<usize>> = Vec::new();
+    let mut by_eta: Vec<usize> = (0..jobs.len()).collect();
+    by_eta.sort_by_key(|&i| (jobs[i].eta, std::cmp::Reverse(jobs[i].weight)));
+    orders.push(by_eta);
+    let mut by_priority: Vec<usize> = (0..jobs.len()).collect();
+    by_priority.sort_by_key(|&i| (std::cmp::Reverse(jobs[i].weight), jobs[i].eta));
+    orders.push(by_priority);
+    let all_orders_tried = jobs.len() <= ALL_ORDERS_LIMIT;
+    if all_orders_tried {
+        permutations(jobs.len(), &mut orders);
+    }
+
+    let (_, assignments, unassigned) = orders
+        .iter()
+        .map(|order| schedule(jobs, lanes, order))
+        .min_by_key(|(cost, _, unassigned)| (unassigned.len(), *cost))
+        .unwrap_or_default();
+
+    BerthPlan {
+        port_id,
+        total_waiting: assignments
+            .iter()
+            .fold(0u64, |total, assignment| total.saturating_add(assignment.waiting)),
+        assignments,
+        unassigned,
+        all_orders_tried,
+    }
+}
+
+metered! {
+#[ic_cdk::update]
+fn apply_plan(plan: BerthPlan) -> Result<Vec<BerthReservation>, Error> {
+    let caller = access::require_role(Role::Operator)?;
+    let payloads = check_plan(&plan)?;
+
+    // one entry per reservation, all carrying the plan that produced them
+    let args = json!({ "plan": &plan });
+    Ok(payloads
+        .into_iter()
+        .map(|payload| {
+            let reservation = reservation::do_reserve(payload, caller);
+            audit::record(
+                "apply_plan",
+                EntityKind::Reservation,
+                Some(reservation.id),
+                args.clone(),
+                None,
+                audit::json(&reservation),
+            );
+            reservation
+        })
+        .collect())
+}
+}
+
+// validates every assignment against the store and the rest of the plan
+// before anything is reserved, and returns the reservations to make.
+fn check_plan(plan: &BerthPlan) -> Result<Vec<ReservationPayload>, Error> {
+    check_plan_size(plan.assignments.len())?;
+    let mut payloads: Vec<ReservationPayload> = Vec::with_capacity(plan.assignments.len());
+    for assignment in &plan.assignments {
+        let payload = ReservationPayload {
+            port_id: plan.port_id,
+            berth_id: assignment.berth_id,
+            ship_id: assignment.ship_id,
+            port_call_id: Some(assignment.port_call_id),
+            start: assignment.start,
+            end: assignment.end,
+        };
+        check_plannable(plan.port_id, assignment.port_call_id)?;
+        reservation::check_reservation(&payload, None)?;
+        if payloads.iter().any(|other| {
+            other.port_call_id == payload.port_call_id
+                || (other.berth_id == payload.berth_id && other.start < payload.end && payload.start < other.end)
+        }) {
+            return Err(Error::Conflict {
+                msg: format!(
+                    "plan books port call with id={} twice or overlaps on berth with id={}",
+                    assignment.port_call_id, assignment.berth_id
+                ),
+            });
+        }
+        payloads.push(payload);
+    }
+    Ok(payloads)
+}
+
+fn check_plan_size(calls: usize) -> Result<(), Error> {
+    if calls > MAX_PLAN_SIZE {
+        return Err(Error::InvalidInput {
+            msg: format!("a plan covers at most {} port calls, not {}", MAX_PLAN_SIZE, calls),
+            violations: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+// places the jobs in the given order, each on the berth where it can start
+// earliest (smallest fitting berth on ties). returns the weighted waiting time,
+// the assignments and the calls that fit nowhere.
+fn schedule(jobs: &[Job], lanes: &[Lane], order: &[usize]) -> (u128, Vec<PlannedBerthing>, Vec<u64>) {
+    let mut lanes = lanes.to_vec();
+    let mut cost: u128 = 0;
+    let mut assignments = Vec::with_capacity(jobs.len());
+    let mut unassigned = Vec::new();
+
+    for &i in order {
+        let job = &jobs[i];
+        let best = lanes
+            .iter()
+            .enumerate()
+            .filter(|(_, lane)| lane.berth.fits(&job.ship))
+            .map(|(index, lane)| (lane.earliest_start(job.eta, job.dwell), lane.berth.length, index))
+            .min_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        match best {
+            Some((start, _, index)) => {
+                let end = start.saturating_add(job.dwell);
+                lanes[index].book(start, end);
+                let waiting = start - job.eta;
+                cost = cost.saturating_add(job.weight.saturating_mul(waiting as u128));
+                assignments.push(PlannedBerthing {
+                    port_call_id: job.port_call_id,
+                    ship_id: job.ship.id,
+                    berth_id: lanes[index].berth.id,
+                    start,
+                    end,
+                    waiting,
+                });
+            }
+            None => unassigned.push(job.port_call_id),
+        }
+    }
+    (cost, assignments, unassigned)
+}
+
+// appends every ordering of 0..n (Heap's algorithm).
+fn permutations(n: usize, orders: &mut Vec<Vec<usize>>) {
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut counters = vec![0; n];
+    orders.push(order.clone());
+    let mut i = 0;
+    while i < n {
+        if counters[i] < i {
+            if i % 2 == 0 {
+                order.swap(0, i);
+            } else {
+                order.swap(counters[i], i);
+            }
+            orders.push(order.clone());
+            counters[i] += 1;
+            i = 0;
+        } else {
+            counters[i] = 0;
+            i += 1;
+        }
+    }
+}
+
+fn load_jobs(port_id: u64, requests: Vec<PlanRequest>) -> Result<Vec<Job>, Error> {
+    let port = match crate::_get_port(&port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a port with id={} not found", port_id),
+            })
+        }
+    };
+    crate::check_open(&port)?;
+    let mut jobs: Vec<Job> = Vec::with_capacity(requests.len());
+    for request in requests {
+        if jobs.iter().any(|job| job.port_call_id == request.port_call_id) {
+            return Err(Error::InvalidInput {
+                msg: format!("port call with id={} is listed twice", request.port_call_id),
+                violations: Vec::new(),
+            });
+        }
+        let call = check_plannable(port_id, request.port_call_id)?;
+        let ship = match ship::_get_ship(&call.ship_id) {
+            Some(ship) => ship,
+            None => {
+                return Err(Error::NotFound {
+                    msg: format!("a ship with id={} not found", call.ship_id),
+                })
+            }
+        };
+        jobs.push(Job {
+            port_call_id: call.id,
+            ship,
+            eta: call.eta,
+            dwell: call.etd - call.eta,
+            weight: request.priority as u128 + 1,
+        });
+    }
+    Ok(jobs)
+}
+
+// a call can be planned while it's announced or approved for this port and
+// has no reservation yet.
+fn check_plannable(port_id: u64, port_call_id: u64) -> Result<port_call::PortCall, Error> {
+    let call = match port_call::_get_port_call(&port_call_id) {
+        Some(call) => call,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a port call with id={} not found", port_call_id),
+            })
+        }
+    };
+    if call.port_id != port_id || !matches!(call.status, PortCallStatus::Announced | PortCallStatus::Approved) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "port call with id={} is not an upcoming call at port with id={}",
+                port_call_id, port_id
+            ),
+            violations: Vec::new(),
+        });
+    }
+    if reservation::has_active_reservation(port_call_id) {
+        return Err(Error::Conflict {
+            msg: format!("port call with id={} already has a berth reservation", port_call_id),
+        });
+    }
+    Ok(call)
+}
+
+fn load_lanes(port_id: u64) -> Vec<Lane> {
+    berth::port_berths(port_id)
+        .into_iter()
+        .filter(|berth| berth.status != BerthStatus::Maintenance)
+        .map(|berth| Lane {
+            busy: reservation::active_reservations(berth.id, 0, u64::MAX)
+                .into_iter()
+                .map(|reservation| (reservation.start, reservation.end))
+                .collect(),
+            berth,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_call::PortCall;
+    use candid::Principal;
+
+    fn berth(id: u64, length: f64) -> Berth {
+        Berth {
+            id,
+            port_id: 1,
+            name: format!("berth {}", id),
+            length,
+            max_draft: 15.0,
+            status: BerthStatus::Available,
+            occupant: None,
+        }
+    }
+
+    fn lane(id: u64, length: f64, busy: Vec<(u64, u64)>) -> Lane {
+        Lane {
+            berth: berth(id, length),
+            busy,
+        }
+    }
+
+    fn ship(id: u64, loa: f64) -> Ship {
+        Ship {
+            id,
+            loa,
+            draft: 10.0,
+            ..Default::default()
+        }
+    }
+
+    fn job(port_call_id: u64, loa: f64, eta: u64, dwell: u64, priority: u32) -> Job {
+        Job {
+            port_call_id,
+            ship: ship(port_call_id, loa),
+            eta,
+            dwell,
+            weight: priority as u128 + 1,
+        }
+    }
+
+    // (port call, berth, start) of each assignment, by port call.
+    fn placements(plan: &BerthPlan) -> Vec<(u64, u64, u64)> {
+        let mut placements: Vec<_> = plan
+            .assignments
+            .iter()
+            .map(|assignment| (assignment.port_call_id, assignment.berth_id, assignment.start))
+            .collect();
+        placements.sort();
+        placements
+    }
+
+    #[test]
+    fn calls_start_at_the_earliest_gap_that_fits_them() {
+        let lane = lane(1, 300.0, vec![(10, 20), (30, 40)]);
+        assert_eq!(lane.earliest_start(0, 10), 0);
+        assert_eq!(lane.earliest_start(0, 11), 40);
+        assert_eq!(lane.earliest_start(15, 10), 20);
+        assert_eq!(lane.earliest_start(45, 100), 45);
+    }
+
+    #[test]
+    fn plans_avoid_booked_slots_and_prefer_the_smallest_fitting_berth() {
+        let lanes = vec![lane(1, 400.0, Vec::new()), lane(2, 250.0, vec![(0, 100)]), lane(3, 200.0, Vec::new())];
+        let jobs = vec![job(10, 180.0, 0, 50, 0), job(11, 240.0, 0, 50, 0), job(12, 500.0, 0, 50, 0)];
+        let plan = best_plan(1, &jobs, &lanes);
+        // berth 2 is booked until 100, so the 240m ship takes berth 1 at once
+        assert_eq!(placements(&plan), vec![(10, 3, 0), (11, 1, 0)]);
+        assert_eq!(plan.unassigned, vec![12]);
+        assert_eq!(plan.total_waiting, 0);
+        assert!(plan.all_orders_tried);
+    }
+
+    #[test]
+    fn urgent_calls_wait_less() {
+        let lanes = vec![lane(1, 300.0, Vec::new())];
+        let jobs = vec![job(10, 200.0, 0, 50, 0), job(11, 200.0, 10, 50, 9)];
+        let plan = best_plan(1, &jobs, &lanes);
+        // the urgent call first costs 60 weighted waiting, against 400 the other way
+        assert_eq!(placements(&plan), vec![(10, 1, 60), (11, 1, 10)]);
+        assert_eq!(plan.total_waiting, 60);
+    }
+
+    #[test]
+    fn every_order_is_tried_for_small_plans_only() {
+        let mut orders = Vec::new();
+        permutations(4, &mut orders);
+        let mut distinct = orders.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!((orders.len(), distinct.len()), (24, 24));
+
+        let lanes = vec![lane(1, 300.0, Vec::new())];
+        let jobs: Vec<Job> = (0..=ALL_ORDERS_LIMIT as u64).map(|id| job(id, 200.0, 0, 10, 0)).collect();
+        let plan = best_plan(1, &jobs, &lanes);
+        assert!(!plan.all_orders_tried);
+        assert_eq!(plan.assignments.len(), jobs.len());
+        assert!(matches!(check_plan_size(MAX_PLAN_SIZE + 1), Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn plans_are_checked_in_full_before_anything_is_reserved() {
+        crate::PORT_STORAGE.with(|service| {
+            service.borrow_mut().insert(
+                1,
+                crate::Port {
+                    id: 1,
+                    capacity: 2,
+                    ..Default::default()
+                },
+            )
+        });
+        berth::do_insert_berth(&berth(1, 300.0));
+        for id in [10, 11] {
+            ship::SHIP_STORAGE.with(|service| service.borrow_mut().insert(id, ship(id, 200.0)));
+            port_call::do_insert_port_call(&PortCall {
+                id,
+                ship_id: id,
+                port_id: 1,
+                status: PortCallStatus::Approved,
+                eta: 0,
+                etd: 50,
+                ata: None,
+                atd: None,
+                created_by: Principal::anonymous(),
+            });
+        }
+        let planned = |port_call_id, start| PlannedBerthing {
+            port_call_id,
+            ship_id: port_call_id,
+            berth_id: 1,
+            start,
+            end: start + 50,
+            waiting: start,
+        };
+        let mut plan = BerthPlan {
+            port_id: 1,
+            assignments: vec![planned(10, 0), planned(11, 25)],
+            unassigned: Vec::new(),
+            total_waiting: 25,
+            all_orders_tried: true,
+        };
+        assert!(matches!(check_plan(&plan), Err(Error::Conflict { .. })));
+        plan.assignments[1] = planned(10, 50);
+        assert!(matches!(check_plan(&plan), Err(Error::Conflict { .. })));
+
+        plan.assignments[1] = planned(11, 50);
+        let payloads = check_plan(&plan).unwrap();
+        assert_eq!(payloads.iter().map(|payload| payload.start).collect::<Vec<_>>(), vec![0, 50]);
+        assert!(!reservation::has_active_reservation(10));
+    }
+}
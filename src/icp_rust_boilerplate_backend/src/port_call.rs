@@ -0,0 +1,395 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::berth::{self, BerthAssignment};
+use crate::metrics::metered;
+use crate::reservation;
+use crate::sequence::{self, Sequence};
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+// Announced -> Approved -> Arrived -> Berthed -> Departed. A call can be
+// Cancelled until the ship has arrived, which also cancels its berth
+// reservations. While a call is Arrived or Berthed, the ship only leaves the
+// port through record_port_call_departure.
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum PortCallStatus {
+    Announced,
+    Approved,
+    Arrived,
+    Berthed,
+    Departed,
+    Cancelled,
+}
+
+impl PortCallStatus {
+    fn can_transition_to(self, next: PortCallStatus) -> bool {
+        use PortCallStatus::*;
+        matches!(
+            (self, next),
+            (Announced, Approved)
+                | (Approved, Arrived)
+                | (Arrived, Berthed)
+                | (Berthed, Departed)
+                | (Announced, Cancelled)
+                | (Approved, Cancelled)
+        )
+    }
+}
+
+// A ship's visit to a port. Estimated times are supplied when the call is
+// announced, actual times are stamped when the ship arrives and departs.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PortCall {
+    pub id: u64,
+    pub ship_id: u64,
+    pub port_id: u64,
+    pub status: PortCallStatus,
+    pub eta: u64,
+    pub etd: u64,
+    pub ata: Option<u64>,
+    pub atd: Option<u64>,
+    pub created_by: Principal,
+}
+
+impl Storable for PortCall {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PortCall {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct PortCallPayload {
+    ship_id: u64,
+    port_id: u64,
+    eta: u64,
+    etd: u64,
+}
+
+thread_local! {
+    static PORT_CALL_STORAGE: RefCell<StableBTreeMap<u64, PortCall, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_port_call(id: u64) -> Result<PortCall, Error> {
+    match _get_port_call(&id) {
+        Some(call) => Ok(call),
+        None => Err(Error::NotFound {
+            msg: format!("a port call with id={} not found", id),
+        }),
+    }
+}
+
+#[ic_cdk::query]
+fn get_port_calls(port_id: u64) -> Vec<PortCall> {
+    PORT_CALL_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, call)| call.port_id == port_id)
+            .map(|(_, call)| call)
+            .collect()
+    })
+}
+
+metered! {
+#[ic_cdk::update]
+fn announce_port_call(payload: PortCallPayload) -> Result<PortCall, Error> {
+    let caller = access::require_role(Role::Operator)?;
+    let args = json!({ "payload": &payload });
+    let port = match crate::_get_port(&payload.port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("couldn't announce a port call. port with id={} not found", payload.port_id),
+            })
+        }
+    };
+    crate::check_open(&port)?;
+    if crate::ship::_get_ship(&payload.ship_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("couldn't announce a port call. ship with id={} not found", payload.ship_id),
+        });
+    }
+    if payload.etd <= payload.eta {
+        return Err(Error::InvalidInput {
+            msg: "etd must be after eta".to_string(),
+            violations: Vec::new(),
+        });
+    }
+
+    let call = PortCall {
+        id: sequence::next_id(Sequence::PortCall),
+        ship_id: payload.ship_id,
+        port_id: payload.port_id,
+        status: PortCallStatus::Announced,
+        eta: payload.eta,
+        etd: payload.etd,
+        ata: None,
+        atd: None,
+        created_by: caller,
+    };
+
+    do_insert_port_call(&call);
+    audit::record("announce_port_call", EntityKind::PortCall, Some(call.id), args, None, audit::json(&call));
+    Ok(call)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn approve_port_call(id: u64) -> Result<PortCall, Error> {
+    access::require_role(Role::PortAuthority)?;
+    let mut call = load_for_transition(id, PortCallStatus::Approved)?;
+    let before = audit::json(&call);
+    call.status = PortCallStatus::Approved;
+    do_insert_port_call(&call);
+    record_transition("approve_port_call", json!({ "id": id }), before, &call);
+    Ok(call)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn cancel_port_call(id: u64) -> Result<PortCall, Error> {
+    access::require_role(Role::Operator)?;
+    let mut call = load_for_transition(id, PortCallStatus::Cancelled)?;
+    let before = audit::json(&call);
+    call.status = PortCallStatus::Cancelled;
+    do_insert_port_call(&call);
+    // the slots booked for the call go back to the calendar
+    for mut booked in reservation::active_for_port_call(id) {
+        let reservation_before = audit::json(&booked);
+        reservation::cancel(&mut booked);
+        audit::record(
+            "cancel_port_call",
+            EntityKind::Reservation,
+            Some(booked.id),
+            json!({ "id": id }),
+            reservation_before,
+            audit::json(&booked),
+        );
+    }
+    record_transition("cancel_port_call", json!({ "id": id }), before, &call);
+    Ok(call)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn record_port_call_arrival(id: u64) -> Result<PortCall, Error> {
+    access::require_role(Role::Operator)?;
+    let mut call = load_for_transition(id, PortCallStatus::Arrived)?;
+    let before = audit::json(&call);
+    // the ship only counts towards the port's occupancy once it's admitted
+    crate::admit_ship(call.port_id, call.ship_id)?;
+    call.status = PortCallStatus::Arrived;
+    call.ata = Some(time());
+    do_insert_port_call(&call);
+    record_transition("record_port_call_arrival", json!({ "id": id }), before, &call);
+    Ok(call)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn record_port_call_berthing(id: u64, berth_id: u64) -> Result<PortCall, Error> {
+    access::require_role(Role::Operator)?;
+    let call = load_for_transition(id, PortCallStatus::Berthed)?;
+    let before = audit::json(&call);
+    // the berth allocation moves the call to Berthed
+    berth::allocate(call.port_id, berth_id, BerthAssignment::PortCall(id))?;
+    let call = get_port_call(id)?;
+    record_transition(
+        "record_port_call_berthing",
+        json!({ "id": id, "berth_id": berth_id }),
+        before,
+        &call,
+    );
+    Ok(call)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn record_port_call_departure(id: u64) -> Result<PortCall, Error> {
+    access::require_role(Role::Operator)?;
+    let mut call = load_for_transition(id, PortCallStatus::Departed)?;
+    let before = audit::json(&call);
+    crate::release_ship(call.port_id, call.ship_id)?;
+    call.status = PortCallStatus::Departed;
+    call.atd = Some(time());
+    do_insert_port_call(&call);
+    record_transition("record_port_call_departure", json!({ "id": id }), before, &call);
+    Ok(call)
+}
+}
+
+fn record_transition(method: &str, args: serde_json::Value, before: Option<String>, call: &PortCall) {
+    audit::record(method, EntityKind::PortCall, Some(call.id), args, before, audit::json(call));
+}
+
+// marks an arrived call as berthed. used by the berth allocation.
+pub(crate) fn mark_berthed(mut call: PortCall) {
+    call.status = PortCallStatus::Berthed;
+    do_insert_port_call(&call);
+}
+
+// loads a port call and checks it may move to `next`.
+fn load_for_transition(id: u64, next: PortCallStatus) -> Result<PortCall, Error> {
+    let call = match _get_port_call(&id) {
+        Some(call) => call,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a port call with id={} not found", id),
+            })
+        }
+    };
+    if !call.status.can_transition_to(next) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "port call with id={} can't move from {:?} to {:?}",
+                id, call.status, next
+            ),
+            violations: Vec::new(),
+        });
+    }
+    Ok(call)
+}
+
+// whether any port call, past or present, was made to the port.
+pub(crate) fn has_port_calls(port_id: u64) -> bool {
+    PORT_CALL_STORAGE.with(|service| service.borrow().iter().any(|(_, call)| call.port_id == port_id))
+}
+
+// whether the principal announced a call that hasn't departed or been cancelled.
+pub(crate) fn has_active_calls_by(principal: &Principal) -> bool {
+    PORT_CALL_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, call)| {
+            call.created_by == *principal
+                && !matches!(call.status, PortCallStatus::Departed | PortCallStatus::Cancelled)
+        })
+    })
+}
+
+// whether the ship has a call that hasn't departed or been cancelled.
+pub(crate) fn has_open_calls_for_ship(ship_id: u64) -> bool {
+    PORT_CALL_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, call)| {
+            call.ship_id == ship_id && !matches!(call.status, PortCallStatus::Departed | PortCallStatus::Cancelled)
+        })
+    })
+}
+
+// a ship with an Arrived or Berthed call in the port has to leave through
+// record_port_call_departure, so the call doesn't get stuck open.
+pub(crate) fn check_no_open_call(port_id: u64, ship_id: u64) -> Result<(), Error> {
+    let open = PORT_CALL_STORAGE.with(|service| {
+        service.borrow().iter().map(|(_, call)| call).find(|call| {
+            call.ship_id == ship_id
+                && call.port_id == port_id
+                && matches!(call.status, PortCallStatus::Arrived | PortCallStatus::Berthed)
+        })
+    });
+    match open {
+        Some(call) => Err(Error::Conflict {
+            msg: format!(
+                "ship with id={} is on port call with id={}; record its departure instead",
+                ship_id, call.id
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+// a helper method to get a port call by id.
+pub(crate) fn _get_port_call(id: &u64) -> Option<PortCall> {
+    PORT_CALL_STORAGE.with(|service| service.borrow().get(id))
+}
+
+// helper method to perform port call insert.
+pub(crate) fn do_insert_port_call(call: &PortCall) {
+    PORT_CALL_STORAGE.with(|service| service.borrow_mut().insert(call.id, call.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PortCallStatus::*;
+
+    const STATUSES: [PortCallStatus; 6] = [Announced, Approved, Arrived, Berthed, Departed, Cancelled];
+
+    fn call(id: u64, ship_id: u64, port_id: u64, status: PortCallStatus) -> PortCall {
+        PortCall {
+            id,
+            ship_id,
+            port_id,
+            status,
+            eta: 10,
+            etd: 20,
+            ata: None,
+            atd: None,
+            created_by: Principal::anonymous(),
+        }
+    }
+
+    #[test]
+    fn calls_move_forward_one_step_or_are_cancelled_before_arrival() {
+        let allowed = [
+            (Announced, Approved),
+            (Approved, Arrived),
+            (Arrived, Berthed),
+            (Berthed, Departed),
+            (Announced, Cancelled),
+            (Approved, Cancelled),
+        ];
+        for from in STATUSES {
+            for to in STATUSES {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_transitions_leave_the_call_unchanged() {
+        do_insert_port_call(&call(1, 5, 2, Arrived));
+        assert!(matches!(load_for_transition(1, Cancelled), Err(Error::InvalidInput { .. })));
+        assert!(matches!(load_for_transition(1, Departed), Err(Error::InvalidInput { .. })));
+        assert!(matches!(load_for_transition(2, Approved), Err(Error::NotFound { .. })));
+        assert_eq!(load_for_transition(1, Berthed).unwrap().status, Arrived);
+        assert_eq!(_get_port_call(&1).unwrap().status, Arrived);
+    }
+
+    #[test]
+    fn ships_on_an_open_call_cant_leave_around_it() {
+        do_insert_port_call(&call(1, 5, 2, Berthed));
+        do_insert_port_call(&call(2, 6, 2, Departed));
+        do_insert_port_call(&call(3, 7, 2, Approved));
+        assert!(matches!(check_no_open_call(2, 5), Err(Error::Conflict { .. })));
+        assert!(check_no_open_call(3, 5).is_ok());
+        assert!(check_no_open_call(2, 6).is_ok());
+        assert!(check_no_open_call(2, 7).is_ok());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::{Error, Memory, Port, PortPage, MEMORY_MANAGER, PORT_STORAGE};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, thread::LocalKey};
+
+// Sort values are cut to this many bytes; ports whose values only differ past
+// the cut are ordered by id, like any other tie.
+const KEY_LEN: usize = 64;
+// Upper bound on ports examined while filtering one page of search results, to
+// stay within the query instruction limit.
+const MAX_SCAN: usize = 10_000;
+
+// (sort value, port id)
+type IndexKey = (Blob<KEY_LEN>, u64);
+type Index = StableBTreeMap<IndexKey, (), Memory>;
+
+// Orders list_ports can return ports in. Names and locations compare
+// case-insensitively.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum PortSort {
+    #[default]
+    Id,
+    Name,
+    Location,
+    Occupancy,
+    FreeCapacity,
+}
+
+const SORTED: [PortSort; 4] = [
+    PortSort::Name,
+    PortSort::Location,
+    PortSort::Occupancy,
+    PortSort::FreeCapacity,
+];
+
+// Filters for search_ports; a port must match all that are set. Results come
+// in the order of the index the search walks, see `search`.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct PortSearch {
+    // compared after normalizing, see `normalize`
+    pub location: Option<String>,
+    pub name_prefix: Option<String>,
+    pub min_free_capacity: Option<u32>,
+}
+
+thread_local! {
+    static PORTS_BY_NAME: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+    ));
+
+    static PORTS_BY_LOCATION: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+    ));
+
+    static PORTS_BY_OCCUPANCY: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+
+    static PORTS_BY_FREE_CAPACITY: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+}
+
+// lowercases and collapses whitespace, so "  Rotterdam " and "rotterdam" match.
+pub(crate) fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+fn index(sort: PortSort) -> Option<&'static LocalKey<RefCell<Index>>> {
+    match sort {
+        PortSort::Id => None,
+        PortSort::Name => Some(&PORTS_BY_NAME),
+        PortSort::Location => Some(&PORTS_BY_LOCATION),
+        PortSort::Occupancy => Some(&PORTS_BY_OCCUPANCY),
+        PortSort::FreeCapacity => Some(&PORTS_BY_FREE_CAPACITY),
+    }
+}
+
+fn key(port: &Port, sort: PortSort) -> IndexKey {
+    let value = match sort {
+        PortSort::Id => Vec::new(),
+        PortSort::Name => normalize(&port.name).into_bytes(),
+        PortSort::Location => normalize(&port.location).into_bytes(),
+        PortSort::Occupancy => port.current_ships.to_be_bytes().to_vec(),
+        PortSort::FreeCapacity => free_capacity(port).to_be_bytes().to_vec(),
+    };
+    (blob(&value), port.id)
+}
+
+fn free_capacity(port: &Port) -> u32 {
+    port.capacity.saturating_sub(port.current_ships)
+}
+
+fn blob(bytes: &[u8]) -> Blob<KEY_LEN> {
+    Blob::try_from(&bytes[..bytes.len().min(KEY_LEN)]).expect("index key within bounds")
+}
+
+// moves a port's index entries from `previous` to `current`. called on every
+// port write and delete.
+pub(crate) fn update(previous: Option<&Port>, current: Option<&Port>) {
+    for sort in SORTED {
+        let (old, new) = (previous.map(|port| key(port, sort)), current.map(|port| key(port, sort)));
+        if old == new {
+            continue;
+        }
+        if let Some(index) = index(sort) {
+            index.with(|index| {
+                let mut index = index.borrow_mut();
+                if let Some(old) = old {
+                    index.remove(&old);
+                }
+                if let Some(new) = new {
+                    index.insert(new, ());
+                }
+            });
+        }
+    }
+}
+
+// rebuilds every index from PORT_STORAGE. run on every upgrade: an index
+// left behind by a version that didn't maintain it can be stale even when it
+// holds the right number of entries.
+pub(crate) fn rebuild() {
+    for sort in SORTED {
+        let index = match index(sort) {
+            Some(index) => index,
+            None => continue,
+        };
+        index.with(|index| {
+            let mut index = index.borrow_mut();
+            let stale: Vec<IndexKey> = index.iter().map(|(key, _)| key).collect();
+            for key in stale {
+                index.remove(&key);
+            }
+            PORT_STORAGE.with(|service| {
+                for (_, port) in service.borrow().iter() {
+                    index.insert(key(&port, sort), ());
+                }
+            });
+        });
+    }
+}
+
+// up to `limit` ports in `sort` order starting at `cursor`. cursors are opaque
+// to callers.
+pub(crate) fn page(sort: PortSort, cursor: Option<&[u8]>, limit: usize) -> Result<PortPage, Error> {
+    let start = match cursor {
+        Some(cursor) => decode_cursor(cursor)?,
+        None => (blob(&[]), 0),
+    };
+    let keys: Vec<IndexKey> = match index(sort) {
+        Some(index) => index.with(|index| {
+            index
+                .borrow()
+                .range(start..)
+                .take(limit + 1)
+                .map(|(key, _)| key)
+                .collect()
+        }),
+        None => PORT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .keys(start.1..)
+                .take(limit + 1)
+                .map(|id| (blob(&[]), id))
+                .collect()
+        }),
+    };
+
+    let next_cursor = keys.get(limit).map(encode_cursor);
+    let ports = keys
+        .iter()
+        .take(limit)
+        .filter_map(|(_, id)| crate::_get_port(id))
+        .collect();
+    Ok(PortPage {
+        ports,
+        next_cursor,
+        total: PORT_STORAGE.with(|service| service.borrow().len()),
+    })
+}
+
+// up to `limit` ports matching every filter. candidates come from the index
+// of whichever filter narrows the search most (location, name prefix or free
+// capacity), counting at most MAX_SCAN entries of each, or from the ports in
+// id order when no filter is set; results are in that index's order. each
+// candidate is checked against the other filters one at a time. a page
+// examines at most MAX_SCAN candidates, so it may come back short with a
+// cursor to carry on from. the cursor remembers the index, so later pages
+// keep to it.
+pub(crate) fn search(filter: &PortSearch, cursor: Option<&[u8]>, limit: usize) -> Result<PortPage, Error> {
+    let location = filter.location.as_deref().map(normalize);
+    let name_prefix = filter.name_prefix.as_deref().map(normalize).filter(|prefix| !prefix.is_empty());
+    let matches = |port: &Port| {
+        location
+            .as_ref()
+            .is_none_or(|location| normalize(&port.location) == *location)
+            && name_prefix
+                .as_ref()
+                .is_none_or(|prefix| normalize(&port.name).starts_with(prefix.as_str()))
+            && filter.min_free_capacity.is_none_or(|min| free_capacity(port) >= min)
+    };
+
+    // the index range each filter covers, as the index and the value its keys
+    // start at. values longer than the key only match up to the cut;
+    // `matches` checks the rest
+    let mut ranges = Vec::new();
+    if let Some(location) = &location {
+        ranges.push((PortSort::Location, blob(location.as_bytes())));
+    }
+    if let Some(prefix) = &name_prefix {
+        ranges.push((PortSort::Name, blob(prefix.as_bytes())));
+    }
+    if let Some(min) = filter.min_free_capacity.filter(|min| *min > 0) {
+        ranges.push((PortSort::FreeCapacity, blob(&min.to_be_bytes())));
+    }
+
+    let (sort, bound, start) = match cursor {
+        Some(cursor) => {
+            let (sort, key) = decode_search_cursor(cursor)?;
+            let bound = match sort {
+                PortSort::Id => blob(&[]),
+                _ => match ranges.iter().find(|(other, _)| *other == sort) {
+                    Some((_, bound)) => *bound,
+                    None => return Err(malformed_cursor()),
+                },
+            };
+            (sort, bound, key)
+        }
+        None => {
+            let (sort, bound) = ranges
+                .iter()
+                .copied()
+                .min_by_key(|(sort, bound)| scan(*sort, (*bound, 0), *bound, |keys| keys.take(MAX_SCAN + 1).count()))
+                .unwrap_or((PortSort::Id, blob(&[])));
+            (sort, bound, (bound, 0))
+        }
+    };
+
+    let (ports, next) = scan(sort, start, bound, |keys| fill(keys, matches, limit));
+    let total = match sort {
+        PortSort::Id => PORT_STORAGE.with(|service| service.borrow().len()),
+        _ => scan(sort, (bound, 0), bound, |keys| keys.count() as u64),
+    };
+    Ok(PortPage {
+        ports,
+        next_cursor: next.map(|key| encode_search_cursor(sort, &key)),
+        total,
+    })
+}
+
+// runs `f` over the keys of `sort`'s index from `start` while they're in the
+// range starting at `bound`: the same value for locations, the same prefix for
+// names, and everything above for free capacity. PortSort::Id walks the ports.
+fn scan<R>(
+    sort: PortSort,
+    start: IndexKey,
+    bound: Blob<KEY_LEN>,
+    f: impl FnOnce(&mut dyn Iterator<Item = IndexKey>) -> R,
+) -> R {
+    let index = match index(sort) {
+        Some(index) => index,
+        None => {
+            return PORT_STORAGE.with(|service| f(&mut service.borrow().keys(start.1..).map(|id| (blob(&[]), id))))
+        }
+    };
+    index.with(|index| {
+        let index = index.borrow();
+        let mut keys = index.range(start..).map(|(key, _)| key).take_while(|(value, _)| match sort {
+            PortSort::Location => *value == bound,
+            PortSort::Name => value.as_slice().starts_with(bound.as_slice()),
+            _ => true,
+        });
+        f(&mut keys)
+    })
+}
+
+// loads candidates until `limit` of them match or MAX_SCAN were examined, and
+// returns the matches with the key to carry on from, if any candidates remain.
+fn fill(
+    candidates: &mut dyn Iterator<Item = IndexKey>,
+    matches: impl Fn(&Port) -> bool,
+    limit: usize,
+) -> (Vec<Port>, Option<IndexKey>) {
+    let mut ports = Vec::new();
+    for (examined, key) in candidates.enumerate() {
+        if ports.len() == limit || examined == MAX_SCAN {
+            return (ports, Some(key));
+        }
+        if let Some(port) = crate::_get_port(&key.1).filter(&matches) {
+            ports.push(port);
+        }
+    }
+    (ports, None)
+}
+
+// a search cursor is the index it walks, as the position of its PortSort,
+// followed by the key to carry on from.
+fn encode_search_cursor(sort: PortSort, key: &IndexKey) -> Vec<u8> {
+    let tag = [PortSort::Id, PortSort::Location, PortSort::Name, PortSort::FreeCapacity]
+        .iter()
+        .position(|other| *other == sort)
+        .unwrap_or(0) as u8;
+    let mut cursor = vec![tag];
+    cursor.extend(encode_cursor(key));
+    cursor
+}
+
+fn decode_search_cursor(cursor: &[u8]) -> Result<(PortSort, IndexKey), Error> {
+    let sort = match cursor.first() {
+        Some(0) => PortSort::Id,
+        Some(1) => PortSort::Location,
+        Some(2) => PortSort::Name,
+        Some(3) => PortSort::FreeCapacity,
+        _ => return Err(malformed_cursor()),
+    };
+    Ok((sort, decode_cursor(&cursor[1..])?))
+}
+
+fn encode_cursor(key: &IndexKey) -> Vec<u8> {
+    let mut cursor = key.0.as_slice().to_vec();
+    cursor.extend_from_slice(&key.1.to_be_bytes());
+    cursor
+}
+
+fn decode_cursor(cursor: &[u8]) -> Result<IndexKey, Error> {
+    if cursor.len() < 8 || cursor.len() > KEY_LEN + 8 {
+        return Err(malformed_cursor());
+    }
+    let (value, id) = cursor.split_at(cursor.len() - 8);
+    let id = u64::from_be_bytes(id.try_into().map_err(|_| malformed_cursor())?);
+    Ok((blob(value), id))
+}
+
+fn malformed_cursor() -> Error {
+    Error::InvalidInput {
+        msg: "malformed cursor".to_string(),
+        violations: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(id: u64, name: &str, location: &str, capacity: u32, current_ships: u32) {
+        let port = Port {
+            id,
+            name: name.to_string(),
+            location: location.to_string(),
+            capacity,
+            current_ships,
+            ..Default::default()
+        };
+        PORT_STORAGE.with(|service| service.borrow_mut().insert(id, port.clone()));
+        update(None, Some(&port));
+    }
+
+    // every page of a search, following cursors until there are none.
+    fn search_all(filter: &PortSearch, limit: usize) -> Vec<Vec<u64>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = search(filter, cursor.as_deref(), limit).unwrap();
+            pages.push(page.ports.iter().map(|port| port.id).collect());
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn searches_page_through_matches_in_index_order() {
+        store(1, "Rotterdam", "Netherlands", 10, 9);
+        store(2, "Antwerp", "Belgium", 10, 0);
+        store(3, "Amsterdam", " netherlands", 10, 2);
+        store(4, "Ijmuiden", "Netherlands", 10, 0);
+        store(5, "Almere", "Netherlands", 4, 3);
+
+        let in_netherlands = PortSearch {
+            location: Some("NETHERLANDS".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search_all(&in_netherlands, 2), vec![vec![1, 3], vec![4, 5]]);
+        assert_eq!(search(&in_netherlands, None, 2).unwrap().total, 4);
+
+        let roomy_a_ports = PortSearch {
+            location: Some("netherlands".to_string()),
+            name_prefix: Some("a".to_string()),
+            min_free_capacity: Some(2),
+        };
+        assert_eq!(search_all(&roomy_a_ports, 1), vec![vec![3], vec![]]);
+
+        let a_ports = PortSearch {
+            name_prefix: Some("A".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search_all(&a_ports, 2), vec![vec![5, 3], vec![2]]);
+        assert_eq!(search(&a_ports, None, 2).unwrap().total, 3);
+
+        let roomy = PortSearch {
+            min_free_capacity: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(search_all(&roomy, 10), vec![vec![3, 2, 4]]);
+
+        // a cursor for another index than the filter's is rejected
+        let cursor = search(&a_ports, None, 1).unwrap().next_cursor.unwrap();
+        assert!(search(&roomy, Some(&cursor), 1).is_err());
+        assert!(search(&a_ports, Some(&[9; 9]), 1).is_err());
+    }
+
+    #[test]
+    fn searches_walk_the_narrowest_index() {
+        for id in 1..=20 {
+            store(id, &format!("Port {}", id), "Netherlands", 10, if id == 7 { 0 } else { 9 });
+        }
+        store(21, "Zeebrugge", "Belgium", 10, 9);
+
+        // one port has room, twenty are in the Netherlands: the free capacity
+        // index is walked, and only one candidate examined
+        let filter = PortSearch {
+            location: Some("Netherlands".to_string()),
+            min_free_capacity: Some(5),
+            ..Default::default()
+        };
+        let page = search(&filter, None, 10).unwrap();
+        assert_eq!(page.ports.iter().map(|port| port.id).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(page.total, 1);
+
+        let filter = PortSearch {
+            location: Some("belgium".to_string()),
+            name_prefix: Some("port".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(&filter, None, 10).unwrap().total, 1);
+    }
+
+    #[test]
+    fn rebuild_replaces_stale_entries() {
+        store(1, "Rotterdam", "Netherlands", 10, 0);
+        // an entry for a port that was renamed while the index wasn't kept
+        PORTS_BY_NAME.with(|index| {
+            let mut index = index.borrow_mut();
+            index.remove(&(blob(b"rotterdam"), 1));
+            index.insert((blob(b"old name"), 1), ());
+        });
+
+        rebuild();
+
+        let names: Vec<IndexKey> = PORTS_BY_NAME.with(|index| index.borrow().iter().map(|(key, _)| key).collect());
+        assert_eq!(names, vec![(blob(b"rotterdam"), 1)]);
+    }
+}
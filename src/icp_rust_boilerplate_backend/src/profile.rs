@@ -0,0 +1,47 @@
+use crate::access;
+use crate::metrics::metered;
+use crate::user_index;
+use crate::{Error, User, UserPatch, UserPayload};
+
+// Self-service endpoints acting on the user owned by the caller.
+
+metered! {
+#[ic_cdk::update]
+fn register_me(payload: UserPayload) -> Result<User, Error> {
+    let caller = access::require_authenticated()?;
+    crate::create_user("register_me", payload, Some(caller))
+}
+}
+
+#[ic_cdk::query]
+fn whoami() -> Result<User, Error> {
+    my_user()
+}
+
+metered! {
+#[ic_cdk::update]
+fn update_my_profile(patch: UserPatch, expected_version: u64) -> Result<User, Error> {
+    let user = my_user()?;
+    crate::check_version("user", user.user_id, user.version, expected_version)?;
+    crate::modify_user("update_my_profile", user, patch)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn delete_me() -> Result<User, Error> {
+    let user = my_user()?;
+    crate::remove_user("delete_me", user)
+}
+}
+
+// the user owned by the caller.
+fn my_user() -> Result<User, Error> {
+    let caller = access::require_authenticated()?;
+    match user_index::find_by_owner(&caller).and_then(|user_id| crate::_get_user(&user_id)) {
+        Some(user) => Ok(user),
+        None => Err(Error::NotFound {
+            msg: format!("principal {} has no registered user", caller),
+        }),
+    }
+}
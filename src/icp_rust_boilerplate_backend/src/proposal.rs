@@ -0,0 +1,603 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::chunked::ChunkedMap;
+use crate::metrics::metered;
+use crate::sequence::{self, Sequence};
+use crate::transfer::{self, TransferRequest};
+use crate::validation::{self, Violations};
+use crate::{Error, Memory, PortPatch, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const DEFAULT_TTL_SECONDS: u64 = 24 * 60 * 60;
+const MAX_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+// Upper bound on proposals examined while filtering one page, to stay within
+// the query instruction limit.
+const MAX_SCAN: usize = 10_000;
+
+// Operations the approval policy can put behind a proposal.
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum Operation {
+    TransferShips,
+    DeletePort,
+    // update_port calls that change the capacity, and adding or removing berths,
+    // which sets it
+    ChangeCapacity,
+}
+
+impl Operation {
+    // the role needed to propose the operation, same as calling it directly.
+    fn required_role(self) -> Role {
+        match self {
+            Operation::TransferShips => Role::Admin,
+            Operation::DeletePort | Operation::ChangeCapacity => Role::PortAuthority,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ProposalAction {
+    TransferShips(TransferRequest),
+    DeletePort { port_id: u64 },
+    ChangeCapacity { port_id: u64, capacity: u32 },
+}
+
+impl ProposalAction {
+    fn operation(&self) -> Operation {
+        match self {
+            ProposalAction::TransferShips(_) => Operation::TransferShips,
+            ProposalAction::DeletePort { .. } => Operation::DeletePort,
+            ProposalAction::ChangeCapacity { .. } => Operation::ChangeCapacity,
+        }
+    }
+}
+
+// Which operations need approval, and from whom. Operations not listed are
+// called directly as before.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApprovalPolicy {
+    pub operations: Vec<Operation>,
+    // admins whose approvals count
+    pub approvers: Vec<Principal>,
+    // approvals needed before the action runs
+    pub threshold: u32,
+    // how long a proposal stays open
+    pub ttl_seconds: u64,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        ApprovalPolicy {
+            operations: Vec::new(),
+            approvers: Vec::new(),
+            threshold: 1,
+            ttl_seconds: DEFAULT_TTL_SECONDS,
+        }
+    }
+}
+
+impl Storable for ApprovalPolicy {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ProposalStatus {
+    Open,
+    // reached the threshold and ran
+    Executed,
+    // reached the threshold, but the action failed against the state at the time
+    Failed,
+    // too many rejections left the threshold out of reach
+    Rejected,
+    Expired,
+}
+
+// Approvers and threshold are copied from the policy when the proposal is
+// created, so policy changes don't affect proposals already open.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Proposal {
+    pub id: u64,
+    pub action: ProposalAction,
+    pub proposer: Principal,
+    pub approvers: Vec<Principal>,
+    pub threshold: u32,
+    pub approvals: Vec<Principal>,
+    pub rejections: Vec<Principal>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub status: ProposalStatus,
+    pub executed_at: Option<u64>,
+    // why the action failed, for Failed proposals
+    pub failure: Option<Error>,
+}
+
+impl Storable for Proposal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Proposal {
+    // marks an open proposal past its deadline as expired. returns whether it did.
+    fn expire(&mut self, now: u64) -> bool {
+        if self.status == ProposalStatus::Open && now >= self.expires_at {
+            self.status = ProposalStatus::Expired;
+            return true;
+        }
+        false
+    }
+}
+
+// Proposals in id order. Pass `next_cursor` back as `cursor` to continue; it
+// is None on the last page.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProposalPage {
+    proposals: Vec<Proposal>,
+    next_cursor: Option<u64>,
+    total: u64,
+}
+
+thread_local! {
+    // transfer proposals list the ships they move, so they're unbounded in size
+    static PROPOSAL_STORAGE: RefCell<ChunkedMap<Proposal>> = RefCell::new(ChunkedMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))),
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))),
+    ));
+
+    pub(crate) static APPROVAL_POLICY: RefCell<Cell<ApprovalPolicy, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))), ApprovalPolicy::default())
+            .expect("cannot init approval policy")
+    );
+}
+
+#[ic_cdk::query]
+fn get_approval_policy() -> ApprovalPolicy {
+    policy()
+}
+
+metered! {
+#[ic_cdk::update]
+fn set_approval_policy(policy: ApprovalPolicy) -> Result<ApprovalPolicy, Error> {
+    access::require_role(Role::Admin)?;
+    validate_policy(&policy)?;
+    let before = audit::json(&self::policy());
+    APPROVAL_POLICY
+        .with(|cell| cell.borrow_mut().set(policy.clone()))
+        .expect("cannot store approval policy");
+    audit::record(
+        "set_approval_policy",
+        EntityKind::Settings,
+        None,
+        json!({ "policy": &policy }),
+        before,
+        audit::json(&policy),
+    );
+    Ok(policy)
+}
+}
+
+#[ic_cdk::query]
+fn get_proposal(id: u64) -> Result<Proposal, Error> {
+    access::require_role(Role::Viewer)?;
+    match _get_proposal(&id) {
+        Some(mut proposal) => {
+            proposal.expire(time());
+            Ok(proposal)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("a proposal with id={} not found", id),
+        }),
+    }
+}
+
+#[ic_cdk::query]
+fn list_proposals(status: Option<ProposalStatus>, cursor: Option<u64>, limit: u64) -> Result<ProposalPage, Error> {
+    access::require_role(Role::Viewer)?;
+    Ok(page(status, cursor, limit, time()))
+}
+
+// proposals as they stand at `now`, expiring the overdue ones in the copies
+// returned.
+fn page(status: Option<ProposalStatus>, cursor: Option<u64>, limit: u64, now: u64) -> ProposalPage {
+    let limit = limit.clamp(1, crate::MAX_PAGE_SIZE) as usize;
+    PROPOSAL_STORAGE.with(|service| {
+        let service = service.borrow();
+        let mut proposals = Vec::new();
+        let mut next_cursor = None;
+        for (scanned, (id, mut proposal)) in service.range(cursor.unwrap_or(0)..).enumerate() {
+            if proposals.len() == limit || scanned == MAX_SCAN {
+                next_cursor = Some(id);
+                break;
+            }
+            proposal.expire(now);
+            if status.is_none_or(|status| status == proposal.status) {
+                proposals.push(proposal);
+            }
+        }
+        ProposalPage {
+            proposals,
+            next_cursor,
+            total: service.len(),
+        }
+    })
+}
+
+metered! {
+#[ic_cdk::update]
+fn create_proposal(action: ProposalAction) -> Result<Proposal, Error> {
+    let operation = action.operation();
+    let caller = access::require_role(operation.required_role())?;
+    let policy = policy();
+    if !policy.operations.contains(&operation) {
+        return Err(Error::InvalidInput {
+            msg: format!("{:?} doesn't require approval, call it directly", operation),
+            violations: Vec::new(),
+        });
+    }
+    // reject what would fail anyway; the action is checked again when it runs
+    check_action(&action)?;
+    let args = json!({ "action": &action });
+    let now = time();
+    let mut proposal = Proposal {
+        id: sequence::next_id(Sequence::Proposal),
+        action,
+        proposer: caller,
+        approvers: policy.approvers,
+        threshold: policy.threshold,
+        approvals: Vec::new(),
+        rejections: Vec::new(),
+        created_at: now,
+        expires_at: now.saturating_add(policy.ttl_seconds.saturating_mul(NANOS_PER_SECOND)),
+        status: ProposalStatus::Open,
+        executed_at: None,
+        failure: None,
+    };
+    // proposing counts as approving for designated approvers
+    if may_vote(&proposal, &caller) {
+        approve("create_proposal", &mut proposal, caller);
+    }
+
+    do_insert_proposal(&proposal);
+    audit::record(
+        "create_proposal",
+        EntityKind::Proposal,
+        Some(proposal.id),
+        args,
+        None,
+        audit::json(&proposal),
+    );
+    Ok(proposal)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn approve_proposal(id: u64) -> Result<Proposal, Error> {
+    let (caller, mut proposal) = ballot(id)?;
+    let before = audit::json(&proposal);
+    approve("approve_proposal", &mut proposal, caller);
+    do_insert_proposal(&proposal);
+    audit::record(
+        "approve_proposal",
+        EntityKind::Proposal,
+        Some(id),
+        json!({ "id": id }),
+        before,
+        audit::json(&proposal),
+    );
+    Ok(proposal)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn reject_proposal(id: u64) -> Result<Proposal, Error> {
+    let (caller, mut proposal) = ballot(id)?;
+    let before = audit::json(&proposal);
+    reject(&mut proposal, caller);
+    do_insert_proposal(&proposal);
+    audit::record(
+        "reject_proposal",
+        EntityKind::Proposal,
+        Some(id),
+        json!({ "id": id }),
+        before,
+        audit::json(&proposal),
+    );
+    Ok(proposal)
+}
+}
+
+// guard for the direct endpoints of operations the policy puts behind a
+// proposal.
+pub(crate) fn require_direct(operation: Operation) -> Result<(), Error> {
+    let policy = policy();
+    if policy.operations.contains(&operation) {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "{:?} requires {} approvals, submit it with create_proposal",
+                operation, policy.threshold
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn policy() -> ApprovalPolicy {
+    APPROVAL_POLICY.with(|policy| policy.borrow().get().clone())
+}
+
+fn validate_policy(policy: &ApprovalPolicy) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    for (index, operation) in policy.operations.iter().enumerate() {
+        if policy.operations[..index].contains(operation) {
+            violations.add("operations", format!("lists {:?} twice", operation));
+        }
+    }
+    for (index, approver) in policy.approvers.iter().enumerate() {
+        if *approver == Principal::anonymous() {
+            violations.add("approvers", "must not include the anonymous principal");
+        } else if policy.approvers[..index].contains(approver) {
+            violations.add("approvers", format!("lists {} twice", approver));
+        } else if access::role_of(approver) != Some(Role::Admin) {
+            violations.add("approvers", format!("{} is not an admin", approver));
+        }
+    }
+    if policy.threshold == 0 {
+        violations.add("threshold", "must be at least 1");
+    } else if !policy.operations.is_empty() && policy.threshold as usize > policy.approvers.len() {
+        violations.add("threshold", "must not exceed the number of approvers");
+    }
+    if !(1..=MAX_TTL_SECONDS).contains(&policy.ttl_seconds) {
+        violations.add("ttl_seconds", format!("must be between 1 and {}", MAX_TTL_SECONDS));
+    }
+    violations.into_result("approval policy")
+}
+
+// validates an action against the current state without changing anything.
+fn check_action(action: &ProposalAction) -> Result<(), Error> {
+    match action {
+        ProposalAction::TransferShips(request) => transfer::check_transfer(request).map(|_| ()),
+        ProposalAction::DeletePort { port_id } | ProposalAction::ChangeCapacity { port_id, .. } => {
+            if crate::_get_port(port_id).is_none() {
+                return Err(Error::NotFound {
+                    msg: format!("a port with id={} not found", port_id),
+                });
+            }
+            if let ProposalAction::ChangeCapacity { capacity, .. } = action {
+                let mut violations = Violations::default();
+                validation::check_capacity(&mut violations, *capacity);
+                violations.into_result("capacity")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// the caller and the proposal, if the caller may still vote on it. a proposal
+// found past its deadline is stored as expired.
+fn ballot(id: u64) -> Result<(Principal, Proposal), Error> {
+    let caller = access::require_role(Role::Admin)?;
+    let mut proposal = match _get_proposal(&id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a proposal with id={} not found", id),
+            })
+        }
+    };
+    if proposal.expire(time()) {
+        do_insert_proposal(&proposal);
+    }
+    if proposal.status != ProposalStatus::Open {
+        return Err(Error::Conflict {
+            msg: format!("proposal with id={} is {:?}", id, proposal.status),
+        });
+    }
+    if !may_vote(&proposal, &caller) {
+        return Err(Error::Unauthorized {
+            msg: format!("caller {} is not an approver of proposal with id={}", caller, id),
+        });
+    }
+    if proposal.approvals.contains(&caller) || proposal.rejections.contains(&caller) {
+        return Err(Error::Conflict {
+            msg: format!("caller {} already voted on proposal with id={}", caller, id),
+        });
+    }
+    Ok((caller, proposal))
+}
+
+// designated approvers only count while they're still admins.
+fn may_vote(proposal: &Proposal, principal: &Principal) -> bool {
+    proposal.approvers.contains(principal) && access::role_of(principal) == Some(Role::Admin)
+}
+
+// adds an approval and runs the action once the threshold is reached.
+fn approve(method: &str, proposal: &mut Proposal, approver: Principal) {
+    proposal.approvals.push(approver);
+    if (proposal.approvals.len() as u32) < proposal.threshold {
+        return;
+    }
+    proposal.executed_at = Some(time());
+    match execute(method, proposal, approver) {
+        Ok(()) => proposal.status = ProposalStatus::Executed,
+        // kept on the proposal rather than returned, so the votes still count
+        Err(error) => {
+            proposal.status = ProposalStatus::Failed;
+            proposal.failure = Some(error);
+        }
+    }
+}
+
+// adds a rejection and closes the proposal once the approvers who haven't
+// rejected it can no longer reach the threshold.
+fn reject(proposal: &mut Proposal, rejecter: Principal) {
+    proposal.rejections.push(rejecter);
+    let remaining = proposal.approvers.len() - proposal.rejections.len();
+    if (remaining as u32) < proposal.threshold {
+        proposal.status = ProposalStatus::Rejected;
+    }
+}
+
+fn execute(method: &str, proposal: &Proposal, approver: Principal) -> Result<(), Error> {
+    match proposal.action.clone() {
+        ProposalAction::TransferShips(request) => {
+            if transfer::replayed(proposal.proposer, &request)?.is_some() {
+                return Ok(());
+            }
+            let ship_ids = transfer::check_transfer(&request)?;
+            transfer::execute(method, request, ship_ids, proposal.proposer, Some(approver));
+            Ok(())
+        }
+        ProposalAction::DeletePort { port_id } => crate::remove_port(method, port_id).map(|_| ()),
+        ProposalAction::ChangeCapacity { port_id, capacity } => {
+            let patch = PortPatch {
+                capacity: Some(capacity),
+                ..Default::default()
+            };
+            crate::apply_port_patch(method, port_id, patch).map(|_| ())
+        }
+    }
+}
+
+// helper method to perform proposal insert.
+fn do_insert_proposal(proposal: &Proposal) {
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal.clone()));
+}
+
+// a helper method to get a proposal by id.
+fn _get_proposal(id: &u64) -> Option<Proposal> {
+    PROPOSAL_STORAGE.with(|service| service.borrow().get(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte; 29])
+    }
+
+    fn proposal(id: u64, threshold: u32, expires_at: u64) -> Proposal {
+        Proposal {
+            id,
+            action: ProposalAction::DeletePort { port_id: 1 },
+            proposer: principal(1),
+            approvers: vec![principal(1), principal(2), principal(3)],
+            threshold,
+            approvals: Vec::new(),
+            rejections: Vec::new(),
+            created_at: 0,
+            expires_at,
+            status: ProposalStatus::Open,
+            executed_at: None,
+            failure: None,
+        }
+    }
+
+    fn violated_fields(policy: &ApprovalPolicy) -> Vec<String> {
+        match validate_policy(policy) {
+            Ok(()) => Vec::new(),
+            Err(Error::InvalidInput { violations, .. }) => violations.into_iter().map(|v| v.field).collect(),
+            Err(_) => panic!("expected InvalidInput"),
+        }
+    }
+
+    #[test]
+    fn policies_need_a_reachable_threshold_and_a_bounded_ttl() {
+        assert!(violated_fields(&ApprovalPolicy::default()).is_empty());
+        let policy = ApprovalPolicy {
+            operations: vec![Operation::DeletePort, Operation::DeletePort],
+            approvers: vec![Principal::anonymous()],
+            threshold: 2,
+            ttl_seconds: 0,
+        };
+        assert_eq!(
+            violated_fields(&policy),
+            vec!["operations", "approvers", "threshold", "ttl_seconds"]
+        );
+        let policy = ApprovalPolicy {
+            threshold: 0,
+            ttl_seconds: MAX_TTL_SECONDS + 1,
+            ..Default::default()
+        };
+        assert_eq!(violated_fields(&policy), vec!["threshold", "ttl_seconds"]);
+    }
+
+    #[test]
+    fn operations_behind_the_policy_cant_be_called_directly() {
+        assert!(require_direct(Operation::TransferShips).is_ok());
+        let policy = ApprovalPolicy {
+            operations: vec![Operation::TransferShips],
+            ..Default::default()
+        };
+        APPROVAL_POLICY.with(|cell| cell.borrow_mut().set(policy)).unwrap();
+        assert!(matches!(require_direct(Operation::TransferShips), Err(Error::Unauthorized { .. })));
+        assert!(require_direct(Operation::DeletePort).is_ok());
+    }
+
+    #[test]
+    fn proposals_are_rejected_once_the_threshold_is_out_of_reach() {
+        let mut two_of_three = proposal(1, 2, 100);
+        reject(&mut two_of_three, principal(1));
+        assert_eq!(two_of_three.status, ProposalStatus::Open);
+        reject(&mut two_of_three, principal(2));
+        assert_eq!(two_of_three.status, ProposalStatus::Rejected);
+
+        let mut three_of_three = proposal(2, 3, 100);
+        reject(&mut three_of_three, principal(3));
+        assert_eq!(three_of_three.status, ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn only_open_proposals_expire_at_their_deadline() {
+        let mut open = proposal(1, 2, 100);
+        assert!(!open.expire(99));
+        assert!(open.expire(100));
+        assert_eq!(open.status, ProposalStatus::Expired);
+        let mut rejected = proposal(2, 2, 100);
+        rejected.status = ProposalStatus::Rejected;
+        assert!(!rejected.expire(200));
+        assert_eq!(rejected.status, ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn proposal_pages_filter_by_status_as_of_now() {
+        for id in 1..=5 {
+            let mut proposal = proposal(id, 2, id * 10);
+            if id == 2 {
+                proposal.status = ProposalStatus::Executed;
+            }
+            do_insert_proposal(&proposal);
+        }
+        let ids = |page: &ProposalPage| page.proposals.iter().map(|proposal| proposal.id).collect::<Vec<_>>();
+
+        // at 30, proposals 1 and 3 are past their deadline
+        let first = page(Some(ProposalStatus::Open), None, 1, 30);
+        assert_eq!((ids(&first), first.next_cursor, first.total), (vec![4], Some(5), 5));
+        let second = page(Some(ProposalStatus::Open), first.next_cursor, 1, 30);
+        assert_eq!((ids(&second), second.next_cursor), (vec![5], None));
+        let expired = page(Some(ProposalStatus::Expired), None, 10, 30);
+        assert_eq!((ids(&expired), expired.next_cursor), (vec![1, 3], None));
+        // expiring on read doesn't store anything
+        assert_eq!(_get_proposal(&1).unwrap().status, ProposalStatus::Open);
+
+        let all = page(None, Some(2), 2, 0);
+        assert_eq!((ids(&all), all.next_cursor), (vec![2, 3], Some(4)));
+    }
+}
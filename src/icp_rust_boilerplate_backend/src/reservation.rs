@@ -0,0 +1,481 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::berth::{self, BerthStatus};
+use crate::metrics::metered;
+use crate::sequence::{self, Sequence};
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum ReservationStatus {
+    Active,
+    Cancelled,
+}
+
+// A booked berth window [start, end) for an expected ship, optionally tied to
+// its port call. Timestamps are nanoseconds since the epoch, like ic_cdk::api::time.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BerthReservation {
+    pub id: u64,
+    pub port_id: u64,
+    pub berth_id: u64,
+    pub ship_id: u64,
+    pub port_call_id: Option<u64>,
+    pub start: u64,
+    pub end: u64,
+    pub status: ReservationStatus,
+    pub created_by: Principal,
+}
+
+impl Storable for BerthReservation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BerthReservation {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ReservationPayload {
+    pub port_id: u64,
+    pub berth_id: u64,
+    pub ship_id: u64,
+    pub port_call_id: Option<u64>,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FreeWindow {
+    berth_id: u64,
+    start: u64,
+    end: u64,
+}
+
+thread_local! {
+    static RESERVATION_STORAGE: RefCell<StableBTreeMap<u64, BerthReservation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+    ));
+
+    // (berth_id, start) -> reservation id for active reservations, so a berth's
+    // calendar can be read in start order.
+    static BERTH_CALENDAR: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_reservation(id: u64) -> Result<BerthReservation, Error> {
+    match _get_reservation(&id) {
+        Some(reservation) => Ok(reservation),
+        None => Err(Error::NotFound {
+            msg: format!("a reservation with id={} not found", id),
+        }),
+    }
+}
+
+#[ic_cdk::query]
+fn get_berth_reservations(berth_id: u64, from: u64, to: u64) -> Vec<BerthReservation> {
+    active_reservations(berth_id, from, to)
+}
+
+// free windows of at least `min_duration` within [from, to) on every berth of the
+// port that a ship of the given LOA and draft fits.
+#[ic_cdk::query]
+fn find_free_berth_windows(
+    port_id: u64,
+    loa: f64,
+    draft: f64,
+    from: u64,
+    to: u64,
+    min_duration: u64,
+) -> Result<Vec<FreeWindow>, Error> {
+    if crate::_get_port(&port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a port with id={} not found", port_id),
+        });
+    }
+    check_window(from, to)?;
+
+    let mut windows = Vec::new();
+    for berth in berth::port_berths(port_id) {
+        if berth.status == BerthStatus::Maintenance || loa > berth.length || draft > berth.max_draft {
+            continue;
+        }
+        let mut cursor = from;
+        for reservation in active_reservations(berth.id, from, to) {
+            if reservation.start > cursor && reservation.start - cursor >= min_duration {
+                windows.push(FreeWindow {
+                    berth_id: berth.id,
+                    start: cursor,
+                    end: reservation.start,
+                });
+            }
+            cursor = cursor.max(reservation.end);
+        }
+        if to > cursor && to - cursor >= min_duration {
+            windows.push(FreeWindow {
+                berth_id: berth.id,
+                start: cursor,
+                end: to,
+            });
+        }
+    }
+    Ok(windows)
+}
+
+metered! {
+#[ic_cdk::update]
+fn reserve_berth(payload: ReservationPayload) -> Result<BerthReservation, Error> {
+    let caller = access::require_role(Role::Operator)?;
+    check_reservation(&payload, None)?;
+    let args = json!({ "payload": &payload });
+    let reservation = do_reserve(payload, caller);
+    audit::record(
+        "reserve_berth",
+        EntityKind::Reservation,
+        Some(reservation.id),
+        args,
+        None,
+        audit::json(&reservation),
+    );
+    Ok(reservation)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn cancel_reservation(id: u64) -> Result<BerthReservation, Error> {
+    access::require_role(Role::Operator)?;
+    let mut reservation = find_active(id)?;
+    access::require_owner_or_role(Some(reservation.created_by), Role::PortAuthority)?;
+    let before = audit::json(&reservation);
+    cancel(&mut reservation);
+    audit::record(
+        "cancel_reservation",
+        EntityKind::Reservation,
+        Some(id),
+        json!({ "id": id }),
+        before,
+        audit::json(&reservation),
+    );
+    Ok(reservation)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn reschedule_reservation(id: u64, start: u64, end: u64) -> Result<BerthReservation, Error> {
+    access::require_role(Role::Operator)?;
+    let mut reservation = find_active(id)?;
+    access::require_owner_or_role(Some(reservation.created_by), Role::PortAuthority)?;
+    let payload = ReservationPayload {
+        port_id: reservation.port_id,
+        berth_id: reservation.berth_id,
+        ship_id: reservation.ship_id,
+        port_call_id: reservation.port_call_id,
+        start,
+        end,
+    };
+    check_reservation(&payload, Some(id))?;
+    let before = audit::json(&reservation);
+
+    BERTH_CALENDAR.with(|calendar| {
+        let mut calendar = calendar.borrow_mut();
+        calendar.remove(&(reservation.berth_id, reservation.start));
+        calendar.insert((reservation.berth_id, start), id);
+    });
+    reservation.start = start;
+    reservation.end = end;
+    do_insert_reservation(&reservation);
+    audit::record(
+        "reschedule_reservation",
+        EntityKind::Reservation,
+        Some(id),
+        json!({ "id": id, "start": start, "end": end }),
+        before,
+        audit::json(&reservation),
+    );
+    Ok(reservation)
+}
+}
+
+// validates a reservation request: the berth exists and isn't under maintenance,
+// the ship fits it, a linked port call belongs to the ship and port, and the
+// window doesn't overlap another active reservation (other than `ignore`).
+pub(crate) fn check_reservation(payload: &ReservationPayload, ignore: Option<u64>) -> Result<(), Error> {
+    check_window(payload.start, payload.end)?;
+    let berth = match berth::_get_berth(payload.port_id, payload.berth_id) {
+        Some(berth) => berth,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!(
+                    "a berth with id={} not found in port with id={}",
+                    payload.berth_id, payload.port_id
+                ),
+            })
+        }
+    };
+    if let Some(port) = crate::_get_port(&payload.port_id) {
+        crate::check_open(&port)?;
+    }
+    if berth.status == BerthStatus::Maintenance {
+        return Err(Error::InvalidInput {
+            msg: format!("berth with id={} is under maintenance", berth.id),
+            violations: Vec::new(),
+        });
+    }
+    let ship = match crate::ship::_get_ship(&payload.ship_id) {
+        Some(ship) => ship,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("a ship with id={} not found", payload.ship_id),
+            })
+        }
+    };
+    if !berth.fits(&ship) {
+        return Err(Error::InvalidInput {
+            msg: format!("ship with id={} doesn't fit berth with id={}", ship.id, berth.id),
+            violations: Vec::new(),
+        });
+    }
+    if let Some(call_id) = payload.port_call_id {
+        match crate::port_call::_get_port_call(&call_id) {
+            Some(call) if call.ship_id == payload.ship_id && call.port_id == payload.port_id => (),
+            Some(_) => {
+                return Err(Error::InvalidInput {
+                    msg: format!("port call with id={} is for a different ship or port", call_id),
+                    violations: Vec::new(),
+                })
+            }
+            None => {
+                return Err(Error::NotFound {
+                    msg: format!("a port call with id={} not found", call_id),
+                })
+            }
+        }
+    }
+    if let Some(other) = active_reservations(payload.berth_id, payload.start, payload.end)
+        .into_iter()
+        .find(|other| Some(other.id) != ignore)
+    {
+        return Err(Error::Conflict {
+            msg: format!(
+                "berth with id={} is already reserved from {} to {} (reservation id={})",
+                payload.berth_id, other.start, other.end, other.id
+            ),
+        });
+    }
+    Ok(())
+}
+
+// stores an already validated reservation.
+pub(crate) fn do_reserve(payload: ReservationPayload, created_by: Principal) -> BerthReservation {
+    let reservation = BerthReservation {
+        id: sequence::next_id(Sequence::Reservation),
+        port_id: payload.port_id,
+        berth_id: payload.berth_id,
+        ship_id: payload.ship_id,
+        port_call_id: payload.port_call_id,
+        start: payload.start,
+        end: payload.end,
+        status: ReservationStatus::Active,
+        created_by,
+    };
+    BERTH_CALENDAR.with(|calendar| {
+        calendar
+            .borrow_mut()
+            .insert((reservation.berth_id, reservation.start), reservation.id)
+    });
+    do_insert_reservation(&reservation);
+    reservation
+}
+
+// active reservations on a berth that overlap [from, to), in start order.
+// a berth's reservations don't overlap, so the only one starting before `from`
+// that can reach into the window is the last one; the scan starts there
+// rather than at the beginning of the berth's history.
+pub(crate) fn active_reservations(berth_id: u64, from: u64, to: u64) -> Vec<BerthReservation> {
+    BERTH_CALENDAR.with(|calendar| {
+        calendar
+            .borrow()
+            .iter_upper_bound(&(berth_id, from))
+            // the entry below the bound may belong to an earlier berth
+            .skip_while(|((berth, _), _)| *berth < berth_id)
+            .take_while(|((berth, start), _)| *berth == berth_id && *start < to)
+            .filter_map(|(_, id)| _get_reservation(&id))
+            .filter(|reservation| reservation.end > from)
+            .collect()
+    })
+}
+
+// frees an active reservation's slot and marks it cancelled.
+pub(crate) fn cancel(reservation: &mut BerthReservation) {
+    BERTH_CALENDAR.with(|calendar| calendar.borrow_mut().remove(&(reservation.berth_id, reservation.start)));
+    reservation.status = ReservationStatus::Cancelled;
+    do_insert_reservation(reservation);
+}
+
+// the active reservations tied to a port call.
+pub(crate) fn active_for_port_call(port_call_id: u64) -> Vec<BerthReservation> {
+    RESERVATION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, reservation)| reservation)
+            .filter(|reservation| {
+                reservation.port_call_id == Some(port_call_id) && reservation.status == ReservationStatus::Active
+            })
+            .collect()
+    })
+}
+
+// whether a port call already holds an active reservation.
+pub(crate) fn has_active_reservation(port_call_id: u64) -> bool {
+    RESERVATION_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, reservation)| {
+            reservation.port_call_id == Some(port_call_id) && reservation.status == ReservationStatus::Active
+        })
+    })
+}
+
+// whether any reservation, active or cancelled, was made in the port.
+pub(crate) fn has_reservations(port_id: u64) -> bool {
+    RESERVATION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .any(|(_, reservation)| reservation.port_id == port_id)
+    })
+}
+
+// whether the principal booked a reservation that is active and not over yet.
+pub(crate) fn has_active_reservations_by(principal: &Principal) -> bool {
+    let now = time();
+    RESERVATION_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, reservation)| {
+            reservation.created_by == *principal
+                && reservation.status == ReservationStatus::Active
+                && reservation.end > now
+        })
+    })
+}
+
+// whether the ship is booked on a reservation that is active and not over yet.
+pub(crate) fn has_active_reservations_for_ship(ship_id: u64) -> bool {
+    let now = time();
+    RESERVATION_STORAGE.with(|service| {
+        service.borrow().iter().any(|(_, reservation)| {
+            reservation.ship_id == ship_id && reservation.status == ReservationStatus::Active && reservation.end > now
+        })
+    })
+}
+
+fn check_window(start: u64, end: u64) -> Result<(), Error> {
+    if start >= end {
+        return Err(Error::InvalidInput {
+            msg: format!("window start {} must be before its end {}", start, end),
+            violations: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+fn find_active(id: u64) -> Result<BerthReservation, Error> {
+    match _get_reservation(&id) {
+        Some(reservation) if reservation.status == ReservationStatus::Active => Ok(reservation),
+        Some(_) => Err(Error::InvalidInput {
+            msg: format!("reservation with id={} is cancelled", id),
+            violations: Vec::new(),
+        }),
+        None => Err(Error::NotFound {
+            msg: format!("a reservation with id={} not found", id),
+        }),
+    }
+}
+
+// a helper method to get a reservation by id.
+pub(crate) fn _get_reservation(id: &u64) -> Option<BerthReservation> {
+    RESERVATION_STORAGE.with(|service| service.borrow().get(id))
+}
+
+// helper method to perform reservation insert.
+fn do_insert_reservation(reservation: &BerthReservation) {
+    RESERVATION_STORAGE.with(|service| service.borrow_mut().insert(reservation.id, reservation.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reserve(berth_id: u64, start: u64, end: u64) -> u64 {
+        let payload = ReservationPayload {
+            port_id: 1,
+            berth_id,
+            ship_id: 1,
+            start,
+            end,
+            ..Default::default()
+        };
+        do_reserve(payload, Principal::anonymous()).id
+    }
+
+    fn ids(berth_id: u64, from: u64, to: u64) -> Vec<u64> {
+        active_reservations(berth_id, from, to).iter().map(|reservation| reservation.id).collect()
+    }
+
+    #[test]
+    fn active_reservations_include_the_one_running_into_the_window() {
+        let other = reserve(1, 0, 1_000);
+        let first = reserve(2, 100, 200);
+        let second = reserve(2, 200, 300);
+        let third = reserve(2, 400, 500);
+        reserve(3, 250, 260);
+
+        assert_eq!(ids(2, 250, 450), vec![second, third]);
+        assert_eq!(ids(2, 150, 160), vec![first]);
+        assert_eq!(ids(2, 300, 400), Vec::<u64>::new());
+        assert_eq!(ids(2, 0, 100), Vec::<u64>::new());
+        assert_eq!(ids(2, 0, u64::MAX), vec![first, second, third]);
+        assert_eq!(ids(1, 500, 600), vec![other]);
+        assert_eq!(ids(1, 1_000, 2_000), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn cancelling_frees_the_slot() {
+        let booked = do_reserve(
+            ReservationPayload {
+                port_id: 1,
+                berth_id: 2,
+                ship_id: 1,
+                port_call_id: Some(9),
+                start: 100,
+                end: 200,
+            },
+            Principal::anonymous(),
+        );
+        reserve(2, 300, 400);
+        let mut linked = active_for_port_call(9);
+        assert_eq!(linked.iter().map(|reservation| reservation.id).collect::<Vec<_>>(), vec![booked.id]);
+
+        cancel(&mut linked[0]);
+        assert!(active_for_port_call(9).is_empty());
+        assert!(!has_active_reservation(9));
+        assert_eq!(ids(2, 100, 200), Vec::<u64>::new());
+        assert_eq!(_get_reservation(&booked.id).unwrap().status, ReservationStatus::Cancelled);
+    }
+}
@@ -0,0 +1,548 @@
+use crate::chunked::ChunkedMap;
+use crate::ship::{self, Ship};
+use crate::{Memory, Port, User, MEMORY_MANAGER, PORT_STORAGE, USER_STORAGE};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Memory as _, StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
+use std::{borrow::Cow, cell::RefCell, thread::LocalKey};
+
+// Stored records are this tag, their layout version and then their candid
+// encoding. Records written before the envelope existed are bare candid (which
+// always starts with "DIDL") and are read as version 1.
+const ENVELOPE_TAG: u8 = 0xe5;
+const BARE_VERSION: u8 = 1;
+
+// A record kept in stable memory whose layout may change between releases.
+// When changing a layout: freeze the old struct under a new name, bump
+// VERSION and teach `decode_older` to turn the frozen layout into the new one.
+pub(crate) trait Versioned: CandidType + DeserializeOwned {
+    // layout version written by this build
+    const VERSION: u8;
+
+    // decodes a payload stored with a layout other than VERSION.
+    fn decode_older(version: u8, payload: &[u8]) -> Result<Self, candid::Error>;
+}
+
+// Stores whose records are rewritten by `migrate`. The discriminant is the
+// store's key in stable memory, so variants may only be appended.
+#[derive(Clone, Copy)]
+enum Store {
+    Ports,
+    Users,
+}
+
+// A record as kept by the bounded maps ports and users were stored in before
+// they moved to chunked storage.
+struct LegacyRecord(Vec<u8>);
+
+impl Storable for LegacyRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        LegacyRecord(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for LegacyRecord {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // store -> layout version its records were last rewritten with
+    static SCHEMA_VERSIONS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+    ));
+}
+
+pub(crate) fn encode<T: Versioned>(record: &T) -> Vec<u8> {
+    let mut bytes = vec![ENVELOPE_TAG, T::VERSION];
+    bytes.extend(Encode!(record).unwrap());
+    bytes
+}
+
+pub(crate) fn decode<T: Versioned>(bytes: &[u8]) -> T {
+    let (version, payload) = match bytes {
+        [ENVELOPE_TAG, version, payload @ ..] => (*version, payload),
+        _ => (BARE_VERSION, bytes),
+    };
+    let record = if version == T::VERSION {
+        Decode!(payload, T)
+    } else {
+        T::decode_older(version, payload)
+    };
+    record.unwrap_or_else(|err| panic!("cannot decode a version {} record: {}", version, err))
+}
+
+// rewrites every record of a store that was last written with an older layout,
+// so later releases only ever need to read the layouts they know about. run
+// from post_upgrade, which records the placeholder ships returned, each with
+// its port's occupancy after it.
+pub(crate) fn migrate() -> Vec<(Ship, u32)> {
+    move_legacy_records(1, &PORT_STORAGE);
+    move_legacy_records(2, &USER_STORAGE);
+    migrate_store(Store::Ports, Port::VERSION, || {
+        PORT_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            let ports: Vec<(u64, Port)> = service.iter().collect();
+            for (id, port) in ports {
+                service.insert(id, port);
+            }
+        })
+    });
+    migrate_store(Store::Users, User::VERSION, || {
+        USER_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            let users: Vec<(u64, User)> = service.iter().collect();
+            for (id, user) in users {
+                service.insert(id, user);
+            }
+        })
+    });
+    reconcile_occupancy()
+}
+
+// Port.current_ships is derived from the ships located in the port. ships
+// counted before they had records get placeholder records, so the occupancy
+// is kept; counters above what the ship index supports, left behind by an
+// interrupted release, are set to it.
+fn reconcile_occupancy() -> Vec<(Ship, u32)> {
+    let mut placeholders = Vec::new();
+    let ports: Vec<(u64, Port)> = PORT_STORAGE.with(|service| service.borrow().iter().collect());
+    for (id, mut port) in ports {
+        let mut current_ships = ship::count_ships_in_port(id);
+        while current_ships < port.current_ships {
+            current_ships += 1;
+            placeholders.push((ship::add_placeholder(id), current_ships));
+        }
+        if port.current_ships != current_ships {
+            port.current_ships = current_ships;
+            PORT_STORAGE.with(|service| service.borrow_mut().insert(id, port));
+        }
+    }
+    placeholders
+}
+
+// moves records out of a bounded map on `memory_id` into chunked storage.
+fn move_legacy_records<V: Storable>(memory_id: u8, target: &'static LocalKey<RefCell<ChunkedMap<V>>>) {
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)));
+    if memory.size() == 0 {
+        return;
+    }
+    let mut legacy: StableBTreeMap<u64, LegacyRecord, Memory> = StableBTreeMap::init(memory);
+    let ids: Vec<u64> = legacy.iter().map(|(id, _)| id).collect();
+    target.with(|target| {
+        let mut target = target.borrow_mut();
+        for id in ids {
+            if let Some(LegacyRecord(bytes)) = legacy.remove(&id) {
+                // ids present in both were already written by this version
+                if !target.contains_key(&id) {
+                    target.insert(id, V::from_bytes(Cow::Owned(bytes)));
+                }
+            }
+        }
+    });
+}
+
+fn migrate_store(store: Store, version: u8, rewrite: impl FnOnce()) {
+    let key = store as u64;
+    // stores that predate this table hold bare records
+    let stored = SCHEMA_VERSIONS.with(|versions| versions.borrow().get(&key)).unwrap_or(0);
+    if stored == version as u64 {
+        return;
+    }
+    rewrite();
+    SCHEMA_VERSIONS.with(|versions| versions.borrow_mut().insert(key, version as u64));
+}
+
+fn unknown_version<T>(version: u8) -> Result<T, candid::Error> {
+    Err(candid::Error::msg(format!("unknown layout version {}", version)))
+}
+
+// Port before it could be archived.
+#[derive(CandidType, Deserialize)]
+struct PortV1 {
+    id: u64,
+    name: String,
+    location: String,
+    capacity: u32,
+    current_ships: u32,
+}
+
+// Port before it recorded its changes.
+#[derive(CandidType, Deserialize)]
+struct PortV2 {
+    id: u64,
+    name: String,
+    location: String,
+    capacity: u32,
+    current_ships: u32,
+    archived_at: Option<u64>,
+}
+
+impl From<PortV1> for PortV2 {
+    fn from(port: PortV1) -> Self {
+        PortV2 {
+            id: port.id,
+            name: port.name,
+            location: port.location,
+            capacity: port.capacity,
+            current_ships: port.current_ships,
+            archived_at: None,
+        }
+    }
+}
+
+// when a record from before change tracking was created is unknown, so it
+// reads as 0; its next change is version 2.
+impl From<PortV2> for Port {
+    fn from(port: PortV2) -> Self {
+        Port {
+            id: port.id,
+            name: port.name,
+            location: port.location,
+            capacity: port.capacity,
+            current_ships: port.current_ships,
+            archived_at: port.archived_at,
+            created_at: 0,
+            updated_at: 0,
+            updated_by: None,
+            version: 1,
+        }
+    }
+}
+
+impl Versioned for Port {
+    const VERSION: u8 = 3;
+
+    fn decode_older(version: u8, payload: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            1 => Ok(PortV2::from(Decode!(payload, PortV1)?).into()),
+            2 => Ok(Decode!(payload, PortV2)?.into()),
+            _ => unknown_version(version),
+        }
+    }
+}
+
+// User before it was bound to a principal.
+#[derive(CandidType, Deserialize)]
+struct UserV1 {
+    user_id: u64,
+    username: String,
+    email: String,
+}
+
+// User before it could be archived.
+#[derive(CandidType, Deserialize)]
+struct UserV2 {
+    user_id: u64,
+    username: String,
+    email: String,
+    owner: Option<Principal>,
+}
+
+// User before it recorded its changes.
+#[derive(CandidType, Deserialize)]
+struct UserV3 {
+    user_id: u64,
+    username: String,
+    email: String,
+    owner: Option<Principal>,
+    archived_at: Option<u64>,
+}
+
+impl From<UserV1> for UserV2 {
+    fn from(user: UserV1) -> Self {
+        UserV2 {
+            user_id: user.user_id,
+            username: user.username,
+            email: user.email,
+            owner: None,
+        }
+    }
+}
+
+impl From<UserV2> for UserV3 {
+    fn from(user: UserV2) -> Self {
+        UserV3 {
+            user_id: user.user_id,
+            username: user.username,
+            email: user.email,
+            owner: user.owner,
+            archived_at: None,
+        }
+    }
+}
+
+impl From<UserV3> for User {
+    fn from(user: UserV3) -> Self {
+        User {
+            user_id: user.user_id,
+            username: user.username,
+            email: user.email,
+            owner: user.owner,
+            archived_at: user.archived_at,
+            created_at: 0,
+            updated_at: 0,
+            updated_by: None,
+            version: 1,
+        }
+    }
+}
+
+impl Versioned for User {
+    const VERSION: u8 = 4;
+
+    fn decode_older(version: u8, payload: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            1 => Ok(UserV3::from(UserV2::from(Decode!(payload, UserV1)?)).into()),
+            2 => Ok(UserV3::from(Decode!(payload, UserV2)?).into()),
+            3 => Ok(Decode!(payload, UserV3)?.into()),
+            _ => unknown_version(version),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+
+    fn port() -> Port {
+        Port {
+            id: 7,
+            name: "Europoort".to_string(),
+            location: "Rotterdam".to_string(),
+            capacity: 12,
+            current_ships: 3,
+            archived_at: Some(42),
+            created_at: 100,
+            updated_at: 200,
+            updated_by: Some(Principal::from_slice(&[4, 5, 6])),
+            version: 3,
+        }
+    }
+
+    fn port_v1() -> PortV1 {
+        PortV1 {
+            id: 7,
+            name: "Europoort".to_string(),
+            location: "Rotterdam".to_string(),
+            capacity: 12,
+            current_ships: 3,
+        }
+    }
+
+    fn user() -> User {
+        User {
+            user_id: 9,
+            username: "harbourmaster".to_string(),
+            email: "hm@example.com".to_string(),
+            owner: Some(Principal::from_slice(&[1, 2, 3])),
+            archived_at: None,
+            created_at: 100,
+            updated_at: 100,
+            updated_by: Some(Principal::from_slice(&[1, 2, 3])),
+            version: 1,
+        }
+    }
+
+    fn user_v1() -> UserV1 {
+        UserV1 {
+            user_id: 9,
+            username: "harbourmaster".to_string(),
+            email: "hm@example.com".to_string(),
+        }
+    }
+
+    fn user_v2() -> UserV2 {
+        UserV2 {
+            user_id: 9,
+            username: "harbourmaster".to_string(),
+            email: "hm@example.com".to_string(),
+            owner: Some(Principal::from_slice(&[1, 2, 3])),
+        }
+    }
+
+    // A later Port layout: `Port` as it is today is frozen and the new layout
+    // adds a required field.
+    #[derive(CandidType, Deserialize)]
+    struct NextPort {
+        id: u64,
+        name: String,
+        location: String,
+        capacity: u32,
+        current_ships: u32,
+        archived_at: Option<u64>,
+        created_at: u64,
+        updated_at: u64,
+        updated_by: Option<Principal>,
+        version: u64,
+        unlocode: String,
+    }
+
+    impl Versioned for NextPort {
+        const VERSION: u8 = Port::VERSION + 1;
+
+        fn decode_older(version: u8, payload: &[u8]) -> Result<Self, candid::Error> {
+            let port = match version {
+                Port::VERSION => Decode!(payload, Port)?,
+                _ => Port::decode_older(version, payload)?,
+            };
+            Ok(NextPort {
+                id: port.id,
+                name: port.name,
+                location: port.location,
+                capacity: port.capacity,
+                current_ships: port.current_ships,
+                archived_at: port.archived_at,
+                created_at: port.created_at,
+                updated_at: port.updated_at,
+                updated_by: port.updated_by,
+                version: port.version,
+                unlocode: String::new(),
+            })
+        }
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct NextUser {
+        user_id: u64,
+        username: String,
+        email: String,
+        owner: Option<Principal>,
+        archived_at: Option<u64>,
+        created_at: u64,
+        updated_at: u64,
+        updated_by: Option<Principal>,
+        version: u64,
+        display_name: String,
+    }
+
+    impl Versioned for NextUser {
+        const VERSION: u8 = User::VERSION + 1;
+
+        fn decode_older(version: u8, payload: &[u8]) -> Result<Self, candid::Error> {
+            let user = match version {
+                User::VERSION => Decode!(payload, User)?,
+                _ => User::decode_older(version, payload)?,
+            };
+            Ok(NextUser {
+                display_name: user.username.clone(),
+                user_id: user.user_id,
+                username: user.username,
+                email: user.email,
+                owner: user.owner,
+                archived_at: user.archived_at,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+                updated_by: user.updated_by,
+                version: user.version,
+            })
+        }
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        let bytes = port().to_bytes().into_owned();
+        assert_eq!(&bytes[..2], &[ENVELOPE_TAG, Port::VERSION]);
+        let decoded = Port::from_bytes(Cow::Owned(bytes));
+        assert_eq!(decoded.name, "Europoort");
+        assert_eq!(decoded.current_ships, 3);
+        assert_eq!(decoded.archived_at, Some(42));
+        assert_eq!(decoded.version, 3);
+        assert_eq!(decoded.updated_by, port().updated_by);
+
+        let decoded = User::from_bytes(user().to_bytes());
+        assert_eq!(decoded.email, "hm@example.com");
+        assert_eq!(decoded.created_at, 100);
+    }
+
+    #[test]
+    fn bare_records_from_before_the_envelope_still_load() {
+        let bare = Encode!(&port_v1()).unwrap();
+        let decoded = Port::from_bytes(Cow::Owned(bare));
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.location, "Rotterdam");
+        assert_eq!(decoded.archived_at, None);
+        assert_eq!(decoded.version, 1);
+
+        let bare = Encode!(&user_v1()).unwrap();
+        let decoded = User::from_bytes(Cow::Owned(bare));
+        assert_eq!(decoded.username, "harbourmaster");
+        assert_eq!(decoded.owner, None);
+    }
+
+    #[test]
+    fn older_users_load_with_defaults_for_new_fields() {
+        let mut stored = vec![ENVELOPE_TAG, 1];
+        stored.extend(Encode!(&user_v1()).unwrap());
+        let decoded: User = decode(&stored);
+        assert_eq!(decoded.email, "hm@example.com");
+        assert_eq!(decoded.owner, None);
+        assert_eq!(encode(&decoded)[1], User::VERSION);
+
+        let mut stored = vec![ENVELOPE_TAG, 2];
+        stored.extend(Encode!(&user_v2()).unwrap());
+        let decoded: User = decode(&stored);
+        assert_eq!(decoded.owner, user().owner);
+        assert_eq!(decoded.archived_at, None);
+        assert_eq!((decoded.created_at, decoded.updated_by, decoded.version), (0, None, 1));
+    }
+
+    #[test]
+    fn archived_ports_keep_their_archival_when_tracking_is_added() {
+        let archived = PortV2 {
+            archived_at: Some(42),
+            ..PortV2::from(port_v1())
+        };
+        let mut stored = vec![ENVELOPE_TAG, 2];
+        stored.extend(Encode!(&archived).unwrap());
+        let decoded: Port = decode(&stored);
+        assert_eq!(decoded.archived_at, Some(42));
+        assert_eq!(decoded.current_ships, 3);
+        assert_eq!((decoded.updated_at, decoded.version), (0, 1));
+    }
+
+    #[test]
+    fn current_records_load_after_a_schema_change() {
+        let stored = port().to_bytes().into_owned();
+        let upgraded: NextPort = decode(&stored);
+        assert_eq!(upgraded.id, 7);
+        assert_eq!(upgraded.name, "Europoort");
+        assert_eq!(upgraded.capacity, 12);
+        assert_eq!(upgraded.archived_at, Some(42));
+        assert_eq!(upgraded.unlocode, "");
+        let rewritten = encode(&upgraded);
+        assert_eq!(rewritten[1], NextPort::VERSION);
+        assert_eq!(decode::<NextPort>(&rewritten).current_ships, 3);
+
+        let upgraded: NextUser = decode(&user().to_bytes());
+        assert_eq!(upgraded.display_name, "harbourmaster");
+        assert_eq!(upgraded.email, "hm@example.com");
+        assert_eq!(upgraded.owner, user().owner);
+
+        let upgraded: NextUser = decode(&Encode!(&user_v1()).unwrap());
+        assert_eq!(upgraded.owner, None);
+    }
+
+    #[test]
+    fn bare_records_load_after_a_schema_change() {
+        let upgraded: NextPort = decode(&Encode!(&port_v1()).unwrap());
+        assert_eq!(upgraded.location, "Rotterdam");
+        assert_eq!(upgraded.archived_at, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown layout version")]
+    fn records_from_a_newer_layout_are_refused() {
+        let mut bytes = encode(&port());
+        bytes[1] = Port::VERSION + 1;
+        let _: Port = decode(&bytes);
+    }
+}
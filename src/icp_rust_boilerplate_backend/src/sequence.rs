@@ -0,0 +1,248 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::metrics::metered;
+use crate::{Error, Memory, ID_COUNTER, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+// Obscured ids have the top bit set, so they never collide with sequential ids
+// handed out before or after a mode switch.
+const OBSCURED_BIT: u64 = 1 << 63;
+const ROUNDS: usize = 4;
+
+// One counter per kind of record. The discriminant is the counter's key in
+// stable memory, so variants may only be appended.
+#[derive(Clone, Copy)]
+pub(crate) enum Sequence {
+    Port,
+    User,
+    Ship,
+    Berth,
+    PortCall,
+    Reservation,
+    Movement,
+    Anchorage,
+    Transfer,
+    Proposal,
+}
+
+// How new record ids are formed from their sequence value.
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum IdMode {
+    // 1, 2, 3, ...
+    #[default]
+    Sequential,
+    // a keyed permutation of the sequence value, so ids don't reveal how many
+    // records exist or were created in between
+    Obscured,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct IdSettings {
+    mode: IdMode,
+    // permutation key from raw_rand; set once and kept across mode switches so
+    // obscured ids stay unique
+    key: Option<Vec<u8>>,
+}
+
+impl Storable for IdSettings {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    // sequence -> next value to hand out
+    static SEQUENCES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+
+    static ID_SETTINGS: RefCell<Cell<IdSettings, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))), IdSettings::default())
+            .expect("cannot init id settings")
+    );
+}
+
+#[ic_cdk::query]
+fn get_id_mode() -> IdMode {
+    ID_SETTINGS.with(|settings| settings.borrow().get().mode)
+}
+
+metered! {
+#[ic_cdk::update]
+async fn set_id_mode(mode: IdMode) -> Result<IdMode, Error> {
+    access::require_role(Role::Admin)?;
+    let mut settings = ID_SETTINGS.with(|settings| settings.borrow().get().clone());
+    let before = audit::json(&settings.mode);
+    if mode == IdMode::Obscured && settings.key.is_none() {
+        let (bytes,) = raw_rand().await.map_err(|(code, msg)| Error::Conflict {
+            msg: format!("couldn't get randomness for the id key: {:?} {}", code, msg),
+        })?;
+        // another call may have stored a key while this one was waiting
+        settings = ID_SETTINGS.with(|settings| settings.borrow().get().clone());
+        settings.key.get_or_insert(bytes);
+    }
+    settings.mode = mode;
+    ID_SETTINGS
+        .with(|cell| cell.borrow_mut().set(settings))
+        .expect("cannot store id settings");
+    audit::record(
+        "set_id_mode",
+        EntityKind::Settings,
+        None,
+        json!({ "mode": mode }),
+        before,
+        audit::json(&mode),
+    );
+    Ok(mode)
+}
+}
+
+// hands out the next value of a sequence. values only grow, so they also order
+// records by creation. a sequence that has never been used continues from the
+// shared counter earlier versions drew every id from, so ids issued before the
+// upgrade are never reused.
+pub(crate) fn next(sequence: Sequence) -> u64 {
+    SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let key = sequence as u64;
+        let value = sequences
+            .get(&key)
+            .unwrap_or_else(|| ID_COUNTER.with(|counter| *counter.borrow().get()).max(1));
+        sequences.insert(key, value + 1);
+        value
+    })
+}
+
+// the id for a new record: the next sequence value, obscured in Obscured mode.
+pub(crate) fn next_id(sequence: Sequence) -> u64 {
+    let value = next(sequence);
+    let settings = ID_SETTINGS.with(|settings| settings.borrow().get().clone());
+    match (settings.mode, settings.key) {
+        (IdMode::Obscured, Some(key)) => obscure(value, &round_keys(&key)),
+        _ => value,
+    }
+}
+
+// maps a sequence value below 2^63 to a unique id at or above it. the Feistel
+// permutation is a bijection on u64; walking its cycle until the result drops
+// below 2^63 keeps it one on the lower half.
+fn obscure(value: u64, keys: &[u64; ROUNDS]) -> u64 {
+    let mut id = permute(value, keys);
+    while id >= OBSCURED_BIT {
+        id = permute(id, keys);
+    }
+    id | OBSCURED_BIT
+}
+
+fn permute(value: u64, keys: &[u64; ROUNDS]) -> u64 {
+    let (mut left, mut right) = ((value >> 32) as u32, value as u32);
+    for key in keys {
+        (left, right) = (right, left ^ mix(right as u64 ^ key) as u32);
+    }
+    ((left as u64) << 32) | right as u64
+}
+
+// splitmix64's finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn round_keys(key: &[u8]) -> [u64; ROUNDS] {
+    let mut keys = [0u64; ROUNDS];
+    for (round, chunk) in key.chunks(8).take(ROUNDS).enumerate() {
+        let mut bytes = [0u8; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        keys[round] = u64::from_le_bytes(bytes);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: [u64; ROUNDS] = [0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210, 42, u64::MAX];
+
+    // undoes one permute round by round
+    fn unpermute(id: u64, keys: &[u64; ROUNDS]) -> u64 {
+        let (mut left, mut right) = ((id >> 32) as u32, id as u32);
+        for key in keys.iter().rev() {
+            (left, right) = (right ^ mix(left as u64 ^ key) as u32, left);
+        }
+        ((left as u64) << 32) | right as u64
+    }
+
+    // walks the cycle back to the sequence value an obscured id came from
+    fn unobscure(id: u64, keys: &[u64; ROUNDS]) -> u64 {
+        let mut value = unpermute(id & !OBSCURED_BIT, keys);
+        while value >= OBSCURED_BIT {
+            value = unpermute(value, keys);
+        }
+        value
+    }
+
+    #[test]
+    fn sequences_continue_from_the_legacy_counter() {
+        // the shared counter had handed out ids 0..=41
+        ID_COUNTER.with(|counter| counter.borrow_mut().set(42)).unwrap();
+
+        assert_eq!((next(Sequence::Port), next(Sequence::Port)), (42, 43));
+        assert_eq!(next(Sequence::Ship), 42);
+        // a sequence in use no longer looks at the counter
+        ID_COUNTER.with(|counter| counter.borrow_mut().set(0)).unwrap();
+        assert_eq!(next(Sequence::Port), 44);
+    }
+
+    #[test]
+    fn unused_sequences_start_at_one() {
+        assert_eq!((next(Sequence::User), next(Sequence::User)), (1, 2));
+    }
+
+    #[test]
+    fn obscuring_is_a_bijection_onto_the_upper_half() {
+        let values = (0..5_000).chain([OBSCURED_BIT - 2, OBSCURED_BIT - 1]);
+        let mut ids = std::collections::BTreeSet::new();
+        for value in values {
+            let id = obscure(value, &KEYS);
+            assert!(id >= OBSCURED_BIT);
+            assert_eq!(unobscure(id, &KEYS), value);
+            assert!(ids.insert(id), "{} collides", id);
+        }
+    }
+
+    #[test]
+    fn cycle_walking_ends_for_any_key() {
+        let keys = [[0; ROUNDS], [u64::MAX; ROUNDS], KEYS, round_keys(&[7; 32])];
+        for keys in &keys {
+            for value in [0, 1, OBSCURED_BIT / 2, OBSCURED_BIT - 1] {
+                assert!(obscure(value, keys) >= OBSCURED_BIT);
+            }
+        }
+    }
+
+    #[test]
+    fn obscured_ids_never_meet_sequential_ones() {
+        ID_SETTINGS
+            .with(|cell| cell.borrow_mut().set(IdSettings { mode: IdMode::Obscured, key: Some(vec![9; 32]) }))
+            .unwrap();
+        let obscured: Vec<u64> = (0..100).map(|_| next_id(Sequence::Berth)).collect();
+        ID_SETTINGS.with(|cell| cell.borrow_mut().set(IdSettings::default())).unwrap();
+        let sequential: Vec<u64> = (0..100).map(|_| next_id(Sequence::Berth)).collect();
+
+        assert!(obscured.iter().all(|id| *id >= OBSCURED_BIT));
+        assert!(sequential.iter().all(|id| *id < OBSCURED_BIT));
+        assert_eq!(sequential[0], 101);
+    }
+}
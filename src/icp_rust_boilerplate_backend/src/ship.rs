@@ -0,0 +1,382 @@
+use crate::access::{self, Role};
+use crate::anchorage;
+use crate::audit::{self, EntityKind};
+use crate::berth;
+use crate::metrics::metered;
+use crate::movement::{self, MovementKind};
+use crate::port_call;
+use crate::reservation;
+use crate::sequence::{self, Sequence};
+use crate::validation;
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub(crate) enum ShipType {
+    Container,
+    Bulk,
+    Tanker,
+    Passenger,
+    RoRo,
+    GeneralCargo,
+    #[default]
+    Other,
+}
+
+// Dimensions are in metres, tonnage in GT.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Ship {
+    pub id: u64,
+    pub imo_number: String,
+    pub name: String,
+    pub flag: String,
+    pub ship_type: ShipType,
+    pub loa: f64,
+    pub beam: f64,
+    pub draft: f64,
+    pub gross_tonnage: u32,
+    pub current_port_id: Option<u64>,
+}
+
+impl Storable for Ship {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Ship {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ShipPayload {
+    pub imo_number: String,
+    pub name: String,
+    pub flag: String,
+    pub ship_type: ShipType,
+    pub loa: f64,
+    pub beam: f64,
+    pub draft: f64,
+    pub gross_tonnage: u32,
+}
+
+thread_local! {
+    pub(crate) static SHIP_STORAGE: RefCell<StableBTreeMap<u64, Ship, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+    ));
+
+    // (port_id, ship_id) for every ship currently in a port. Port.current_ships is
+    // derived from this index.
+    pub(crate) static SHIPS_BY_PORT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_ship(id: u64) -> Result<Ship, Error> {
+    match _get_ship(&id) {
+        Some(ship) => Ok(ship),
+        None => Err(Error::NotFound {
+            msg: format!("a ship with id={} not found", id),
+        }),
+    }
+}
+
+#[ic_cdk::query]
+fn get_all_ships() -> Vec<Ship> {
+    SHIP_STORAGE.with(|service| service.borrow().iter().map(|(_, ship)| ship.clone()).collect())
+}
+
+#[ic_cdk::query]
+fn get_port_ships(port_id: u64) -> Result<Vec<Ship>, Error> {
+    if crate::_get_port(&port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a port with id={} not found", port_id),
+        });
+    }
+    Ok(ship_ids_in_port(port_id).iter().filter_map(_get_ship).collect())
+}
+
+metered! {
+#[ic_cdk::update]
+fn add_ship(payload: ShipPayload) -> Result<Ship, Error> {
+    access::require_role(Role::Operator)?;
+    validation::validate_ship(&payload)?;
+    let args = json!({ "payload": &payload });
+    let ship = Ship {
+        id: sequence::next_id(Sequence::Ship),
+        imo_number: payload.imo_number,
+        name: payload.name,
+        flag: payload.flag,
+        ship_type: payload.ship_type,
+        loa: payload.loa,
+        beam: payload.beam,
+        draft: payload.draft,
+        gross_tonnage: payload.gross_tonnage,
+        current_port_id: None,
+    };
+
+    do_insert_ship(&ship);
+    audit::record("add_ship", EntityKind::Ship, Some(ship.id), args, None, audit::json(&ship));
+    Ok(ship)
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn update_ship(id: u64, payload: ShipPayload) -> Result<Ship, Error> {
+    access::require_role(Role::Operator)?;
+    validation::validate_ship(&payload)?;
+    let args = json!({ "id": id, "payload": &payload });
+    match _get_ship(&id) {
+        Some(mut ship) => {
+            let before = audit::json(&ship);
+            // the location is only changed through arrivals and transfers
+            ship.imo_number = payload.imo_number;
+            ship.name = payload.name;
+            ship.flag = payload.flag;
+            ship.ship_type = payload.ship_type;
+            ship.loa = payload.loa;
+            ship.beam = payload.beam;
+            ship.draft = payload.draft;
+            ship.gross_tonnage = payload.gross_tonnage;
+            do_insert_ship(&ship);
+            audit::record("update_ship", EntityKind::Ship, Some(id), args, before, audit::json(&ship));
+            Ok(ship)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("couldn't update a ship with id={}. ship not found", id),
+        }),
+    }
+}
+}
+
+metered! {
+#[ic_cdk::update]
+fn delete_ship(id: u64) -> Result<Ship, Error> {
+    access::require_role(Role::Operator)?;
+    // calls and bookings would be left pointing at a ship that doesn't exist
+    if port_call::has_open_calls_for_ship(id) {
+        return Err(Error::Conflict {
+            msg: format!("ship with id={} has port calls that haven't departed or been cancelled", id),
+        });
+    }
+    if reservation::has_active_reservations_for_ship(id) {
+        return Err(Error::Conflict {
+            msg: format!("ship with id={} has active berth reservations", id),
+        });
+    }
+    match SHIP_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+        Some(ship) => {
+            anchorage::remove_ship(ship.id);
+            if let Some(port_id) = ship.current_port_id {
+                SHIPS_BY_PORT.with(|index| index.borrow_mut().remove(&(port_id, ship.id)));
+                berth::vacate_ship(port_id, ship.id);
+                if let Some(occupancy) = refresh_current_ships(port_id) {
+                    movement::record(port_id, ship.id, MovementKind::Departure, occupancy);
+                }
+                anchorage::admit_waiting(port_id);
+            }
+            audit::record("delete_ship", EntityKind::Ship, Some(id), json!({ "id": id }), audit::json(&ship), None);
+            Ok(ship)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("couldn't delete a ship with id={}. ship not found.", id),
+        }),
+    }
+}
+}
+
+// a helper method to get a ship by id.
+pub(crate) fn _get_ship(id: &u64) -> Option<Ship> {
+    SHIP_STORAGE.with(|service| service.borrow().get(id))
+}
+
+// helper method to perform ship insert.
+pub(crate) fn do_insert_ship(ship: &Ship) {
+    SHIP_STORAGE.with(|service| service.borrow_mut().insert(ship.id, ship.clone()));
+}
+
+// stores a stand-in for a ship a port counted before ships had records, docked
+// in the port. operators can fill in its particulars with update_ship, and it
+// leaves like any other ship.
+pub(crate) fn add_placeholder(port_id: u64) -> Ship {
+    let ship = Ship {
+        id: sequence::next_id(Sequence::Ship),
+        imo_number: "UNKNOWN".to_string(),
+        name: "Unregistered ship".to_string(),
+        flag: "Unknown".to_string(),
+        current_port_id: Some(port_id),
+        ..Default::default()
+    };
+    do_insert_ship(&ship);
+    SHIPS_BY_PORT.with(|index| index.borrow_mut().insert((port_id, ship.id), ()));
+    ship
+}
+
+// ids of the ships currently located in the given port, in ascending order.
+pub(crate) fn ship_ids_in_port(port_id: u64) -> Vec<u64> {
+    SHIPS_BY_PORT.with(|index| {
+        index
+            .borrow()
+            .range((port_id, 0)..=(port_id, u64::MAX))
+            .map(|((_, ship_id), _)| ship_id)
+            .collect()
+    })
+}
+
+pub(crate) fn ship_count() -> u64 {
+    SHIP_STORAGE.with(|service| service.borrow().len())
+}
+
+pub(crate) fn count_ships_in_port(port_id: u64) -> u32 {
+    SHIPS_BY_PORT.with(|index| index.borrow().range((port_id, 0)..=(port_id, u64::MAX)).count() as u32)
+}
+
+// recomputes Port.current_ships from the ship records located in the port and
+// returns the new value, or None if the port doesn't exist.
+pub(crate) fn refresh_current_ships(port_id: u64) -> Option<u32> {
+    let mut port = crate::_get_port(&port_id)?;
+    port.current_ships = count_ships_in_port(port_id);
+    crate::store_port(&port);
+    Some(port.current_ships)
+}
+
+// moves a ship to `port_id` (or out of any port when `None`), keeping the
+// location index and the occupancy of both ports in sync and recording the
+// departure and arrival events. a ship entering a port leaves any anchorage
+// queue, and the space it frees goes to the next ship waiting for the old port.
+pub(crate) fn relocate_ship(mut ship: Ship, port_id: Option<u64>) -> Ship {
+    let previous = ship.current_port_id;
+    if previous == port_id {
+        return ship;
+    }
+    SHIPS_BY_PORT.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&(previous, ship.id));
+        }
+        if let Some(port_id) = port_id {
+            index.insert((port_id, ship.id), ());
+        }
+    });
+    ship.current_port_id = port_id;
+    do_insert_ship(&ship);
+    if port_id.is_some() {
+        anchorage::remove_ship(ship.id);
+    }
+
+    if let Some(previous) = previous {
+        berth::vacate_ship(previous, ship.id);
+        if let Some(occupancy) = refresh_current_ships(previous) {
+            movement::record(previous, ship.id, MovementKind::Departure, occupancy);
+        }
+    }
+    if let Some(port_id) = port_id {
+        if let Some(occupancy) = refresh_current_ships(port_id) {
+            movement::record(port_id, ship.id, MovementKind::Arrival, occupancy);
+        }
+    }
+    if let Some(previous) = previous {
+        anchorage::admit_waiting(previous);
+    }
+    ship
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_largest_valid_ship_fits_its_storage_bound() {
+        let payload = ShipPayload {
+            imo_number: "IMO 123456789012".to_string(),
+            name: "船".repeat(100),
+            flag: "旗".repeat(50),
+            loa: 400.0,
+            beam: 60.0,
+            draft: 20.0,
+            ..Default::default()
+        };
+        assert!(validation::validate_ship(&payload).is_ok());
+        let ship = Ship {
+            id: u64::MAX,
+            imo_number: payload.imo_number,
+            name: payload.name,
+            flag: payload.flag,
+            loa: payload.loa,
+            current_port_id: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(ship.to_bytes().len() <= Ship::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn ships_need_positive_finite_dimensions_and_bounded_text() {
+        let payload = ShipPayload {
+            imo_number: "IMO-1".to_string(),
+            name: "x".repeat(101),
+            flag: String::new(),
+            loa: f64::NAN,
+            beam: -1.0,
+            draft: f64::INFINITY,
+            ..Default::default()
+        };
+        let violations = match validation::validate_ship(&payload) {
+            Err(Error::InvalidInput { violations, .. }) => violations,
+            _ => panic!("expected InvalidInput"),
+        };
+        let fields: Vec<&str> = violations.iter().map(|violation| violation.field.as_str()).collect();
+        assert_eq!(fields, vec!["imo_number", "name", "flag", "loa", "beam", "draft"]);
+    }
+
+    #[test]
+    fn migration_keeps_legacy_occupancy_with_placeholder_ships() {
+        let port = |id, current_ships| crate::Port {
+            id,
+            name: format!("port {}", id),
+            location: "Rotterdam".to_string(),
+            capacity: 10,
+            current_ships,
+            ..Default::default()
+        };
+        crate::PORT_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            // counters from before ships had records
+            service.insert(1, port(1, 3));
+            service.insert(2, port(2, 0));
+            service.insert(3, port(3, 2));
+        });
+        SHIPS_BY_PORT.with(|index| {
+            let mut index = index.borrow_mut();
+            index.insert((2, 10), ());
+            index.insert((2, 11), ());
+            index.insert((3, 12), ());
+        });
+
+        let placeholders = crate::schema::migrate();
+
+        let occupancy = |id| crate::_get_port(&id).unwrap().current_ships;
+        assert_eq!((occupancy(1), occupancy(2), occupancy(3)), (3, 2, 2));
+        let added: Vec<(Option<u64>, u32)> = placeholders
+            .iter()
+            .map(|(ship, occupancy)| (ship.current_port_id, *occupancy))
+            .collect();
+        assert_eq!(added, vec![(Some(1), 1), (Some(1), 2), (Some(1), 3), (Some(3), 2)]);
+        assert_eq!(ship_ids_in_port(1).len(), 3);
+        assert!(placeholders.iter().all(|(ship, _)| _get_ship(&ship.id).is_some()));
+        // a second upgrade finds nothing left to reconcile
+        assert!(crate::schema::migrate().is_empty());
+    }
+}
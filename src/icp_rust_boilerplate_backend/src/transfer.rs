@@ -0,0 +1,439 @@
+use crate::access::{self, Role};
+use crate::audit::{self, EntityKind};
+use crate::chunked::ChunkedMap;
+use crate::metrics::metered;
+use crate::port_call;
+use crate::proposal::{self, Operation};
+use crate::sequence::{self, Sequence};
+use crate::ship;
+use crate::{Error, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde_json::json;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+
+// Which ships a transfer moves.
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum TransferSelection {
+    // this many ships from the source port, lowest ids first
+    Count(u32),
+    // exactly these ships, all of which must be in the source port
+    Ships(Vec<u64>),
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum TransferStatus {
+    Completed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TransferRequest {
+    pub source_port_id: u64,
+    pub destination_port_id: u64,
+    pub selection: TransferSelection,
+    // retrying a request with the same key returns the original transfer
+    // instead of moving ships again. keys are scoped to the caller.
+    pub idempotency_key: Option<String>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Transfer {
+    pub id: u64,
+    pub source_port_id: u64,
+    pub destination_port_id: u64,
+    pub selection: TransferSelection,
+    // the ships actually moved
+    pub ship_ids: Vec<u64>,
+    pub requested_by: Principal,
+    pub approved_by: Option<Principal>,
+    pub timestamp: u64,
+    pub status: TransferStatus,
+    pub idempotency_key: Option<String>,
+}
+
+impl Storable for Transfer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// (requester principal bytes, idempotency key)
+type IdempotencyKey = (Blob<29>, Blob<MAX_IDEMPOTENCY_KEY_LEN>);
+
+thread_local! {
+    // transfers list every ship they move, so they're unbounded in size
+    static TRANSFER_STORAGE: RefCell<ChunkedMap<Transfer>> = RefCell::new(ChunkedMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))),
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))),
+    ));
+
+    // (port_id, transfer_id) for both ports of every transfer
+    static TRANSFERS_BY_PORT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+
+    static IDEMPOTENCY_KEYS: RefCell<StableBTreeMap<IdempotencyKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+}
+
+#[ic_cdk::query]
+fn get_transfer(id: u64) -> Result<Transfer, Error> {
+    match _get_transfer(&id) {
+        Some(transfer) => Ok(transfer),
+        None => Err(Error::NotFound {
+            msg: format!("a transfer with id={} not found", id),
+        }),
+    }
+}
+
+// transfers into or out of a port, oldest first.
+#[ic_cdk::query]
+fn get_port_transfers(port_id: u64) -> Result<Vec<Transfer>, Error> {
+    if crate::_get_port(&port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("a port with id={} not found", port_id),
+        });
+    }
+    Ok(TRANSFERS_BY_PORT.with(|index| {
+        index
+            .borrow()
+            .range((port_id, 0)..=(port_id, u64::MAX))
+            .filter_map(|((_, transfer_id), _)| _get_transfer(&transfer_id))
+            .collect()
+    }))
+}
+
+metered! {
+#[ic_cdk::update]
+fn transfer_ships_admin(request: TransferRequest) -> Result<Transfer, Error> {
+    // Only admins may move ships between ports
+    let caller = access::require_role(Role::Admin)?;
+    proposal::require_direct(Operation::TransferShips)?;
+    if let Some(transfer) = replayed(caller, &request)? {
+        return Ok(transfer);
+    }
+    let ship_ids = check_transfer(&request)?;
+    Ok(execute("transfer_ships_admin", request, ship_ids, caller, Some(caller)))
+}
+}
+
+// returns the transfer an earlier call with the same idempotency key made, if
+// any. reusing a key for a different request is a Conflict.
+pub(crate) fn replayed(caller: Principal, request: &TransferRequest) -> Result<Option<Transfer>, Error> {
+    let key = match idempotency_key(caller, request)? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let transfer = match IDEMPOTENCY_KEYS
+        .with(|keys| keys.borrow().get(&key))
+        .and_then(|id| _get_transfer(&id))
+    {
+        Some(transfer) => transfer,
+        None => return Ok(None),
+    };
+    if transfer.source_port_id != request.source_port_id
+        || transfer.destination_port_id != request.destination_port_id
+        || transfer.selection != request.selection
+    {
+        return Err(Error::Conflict {
+            msg: format!(
+                "idempotency key was already used for transfer with id={} with different parameters",
+                transfer.id
+            ),
+        });
+    }
+    Ok(Some(transfer))
+}
+
+// validates a transfer against the current state without changing anything,
+// and returns the ships it would move.
+pub(crate) fn check_transfer(request: &TransferRequest) -> Result<Vec<u64>, Error> {
+    if request.source_port_id == request.destination_port_id {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "source and destination are the same port (id={})",
+                request.source_port_id
+            ),
+            violations: Vec::new(),
+        });
+    }
+    if crate::_get_port(&request.source_port_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("source port with id={} not found", request.source_port_id),
+        });
+    }
+    let destination_port = match crate::_get_port(&request.destination_port_id) {
+        Some(port) => port,
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("destination port with id={} not found", request.destination_port_id),
+            })
+        }
+    };
+    crate::check_open(&destination_port)?;
+
+    if request.selection.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "a transfer must move at least one ship".to_string(),
+            violations: Vec::new(),
+        });
+    }
+    let in_source = ship::ship_ids_in_port(request.source_port_id);
+    let ship_ids = match &request.selection {
+        TransferSelection::Count(count) => {
+            if in_source.len() < *count as usize {
+                return Err(Error::InvalidInput {
+                    msg: format!(
+                        "source port with id={} has {} ships, fewer than the {} requested",
+                        request.source_port_id,
+                        in_source.len(),
+                        count
+                    ),
+                    violations: Vec::new(),
+                });
+            }
+            in_source.into_iter().take(*count as usize).collect()
+        }
+        TransferSelection::Ships(ship_ids) => {
+            for (index, ship_id) in ship_ids.iter().enumerate() {
+                if ship_ids[..index].contains(ship_id) {
+                    return Err(Error::InvalidInput {
+                        msg: format!("ship with id={} is listed twice", ship_id),
+                        violations: Vec::new(),
+                    });
+                }
+                if in_source.binary_search(ship_id).is_err() {
+                    return Err(Error::InvalidInput {
+                        msg: format!(
+                            "ship with id={} is not in source port with id={}",
+                            ship_id, request.source_port_id
+                        ),
+                        violations: Vec::new(),
+                    });
+                }
+            }
+            ship_ids.clone()
+        }
+    };
+
+    for ship_id in &ship_ids {
+        port_call::check_no_open_call(request.source_port_id, *ship_id)?;
+    }
+
+    // Check the destination can take the ships
+    if crate::remaining_capacity(&destination_port, ship_ids.len())? < ship_ids.len() as u32 {
+        return Err(Error::CapacityExceeded {
+            msg: format!(
+                "destination port with id={} can't take {} more ships",
+                request.destination_port_id,
+                ship_ids.len()
+            ),
+        });
+    }
+    Ok(ship_ids)
+}
+
+// moves already validated ships and records the transfer.
+pub(crate) fn execute(
+    method: &str,
+    request: TransferRequest,
+    ship_ids: Vec<u64>,
+    requested_by: Principal,
+    approved_by: Option<Principal>,
+) -> Transfer {
+    let args = json!({ "request": &request });
+    let key = idempotency_key(requested_by, &request).ok().flatten();
+    for ship in ship_ids.iter().filter_map(ship::_get_ship) {
+        ship::relocate_ship(ship, Some(request.destination_port_id));
+    }
+
+    let transfer = Transfer {
+        id: sequence::next_id(Sequence::Transfer),
+        source_port_id: request.source_port_id,
+        destination_port_id: request.destination_port_id,
+        selection: request.selection,
+        ship_ids,
+        requested_by,
+        approved_by,
+        timestamp: time(),
+        status: TransferStatus::Completed,
+        idempotency_key: request.idempotency_key,
+    };
+    TRANSFER_STORAGE.with(|service| service.borrow_mut().insert(transfer.id, transfer.clone()));
+    TRANSFERS_BY_PORT.with(|index| {
+        let mut index = index.borrow_mut();
+        index.insert((transfer.source_port_id, transfer.id), ());
+        index.insert((transfer.destination_port_id, transfer.id), ());
+    });
+    if let Some(key) = key {
+        IDEMPOTENCY_KEYS.with(|keys| keys.borrow_mut().insert(key, transfer.id));
+    }
+    audit::record(
+        method,
+        EntityKind::Transfer,
+        Some(transfer.id),
+        args,
+        None,
+        audit::json(&transfer),
+    );
+    transfer
+}
+
+impl TransferSelection {
+    fn is_empty(&self) -> bool {
+        match self {
+            TransferSelection::Count(count) => *count == 0,
+            TransferSelection::Ships(ship_ids) => ship_ids.is_empty(),
+        }
+    }
+}
+
+fn idempotency_key(caller: Principal, request: &TransferRequest) -> Result<Option<IdempotencyKey>, Error> {
+    let key = match &request.idempotency_key {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("idempotency key must be 1 to {} bytes", MAX_IDEMPOTENCY_KEY_LEN),
+            violations: Vec::new(),
+        });
+    }
+    let principal = Blob::try_from(caller.as_slice()).expect("principal within bounds");
+    let key = Blob::try_from(key.as_bytes()).expect("key within bounds");
+    Ok(Some((principal, key)))
+}
+
+// a helper method to get a transfer by id.
+fn _get_transfer(id: &u64) -> Option<Transfer> {
+    TRANSFER_STORAGE.with(|service| service.borrow().get(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Port;
+
+    fn request(source_port_id: u64, destination_port_id: u64, selection: TransferSelection) -> TransferRequest {
+        TransferRequest {
+            source_port_id,
+            destination_port_id,
+            selection,
+            idempotency_key: None,
+        }
+    }
+
+    // port 1 holds ships 10, 11 and 12; port 2 has room for two more; port 3
+    // is archived.
+    fn setup() {
+        let port = |id, capacity, current_ships| Port {
+            id,
+            name: format!("port {}", id),
+            location: "Rotterdam".to_string(),
+            capacity,
+            current_ships,
+            ..Default::default()
+        };
+        crate::PORT_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            service.insert(1, port(1, 10, 3));
+            service.insert(2, port(2, 3, 1));
+            service.insert(
+                3,
+                Port {
+                    archived_at: Some(1),
+                    ..port(3, 10, 0)
+                },
+            );
+        });
+        ship::SHIPS_BY_PORT.with(|index| {
+            let mut index = index.borrow_mut();
+            for ship_id in [10, 11, 12] {
+                index.insert((1, ship_id), ());
+            }
+            index.insert((2, 20), ());
+        });
+    }
+
+    fn check(source: u64, destination: u64, selection: TransferSelection) -> Result<Vec<u64>, Error> {
+        check_transfer(&request(source, destination, selection))
+    }
+
+    #[test]
+    fn transfers_are_validated_before_anything_moves() {
+        setup();
+        use TransferSelection::{Count, Ships};
+        assert!(matches!(check(1, 1, Count(1)), Err(Error::InvalidInput { .. })));
+        assert!(matches!(check(4, 2, Count(1)), Err(Error::NotFound { .. })));
+        assert!(matches!(check(1, 4, Count(1)), Err(Error::NotFound { .. })));
+        assert!(matches!(check(1, 3, Count(1)), Err(Error::Conflict { .. })));
+        assert!(matches!(check(1, 2, Count(0)), Err(Error::InvalidInput { .. })));
+        assert!(matches!(check(1, 2, Ships(Vec::new())), Err(Error::InvalidInput { .. })));
+        assert!(matches!(check(1, 2, Ships(vec![12, 12])), Err(Error::InvalidInput { .. })));
+        assert!(matches!(check(1, 2, Ships(vec![20])), Err(Error::InvalidInput { .. })));
+        assert!(matches!(check(2, 1, Count(2)), Err(Error::InvalidInput { .. })));
+        assert_eq!(check(1, 2, Ships(vec![12, 10])).ok(), Some(vec![12, 10]));
+    }
+
+    #[test]
+    fn transfers_fill_the_destination_but_never_past_its_capacity() {
+        setup();
+        use TransferSelection::{Count, Ships};
+        assert_eq!(check(1, 2, Count(2)).ok(), Some(vec![10, 11]));
+        assert!(matches!(check(1, 2, Count(3)), Err(Error::CapacityExceeded { .. })));
+        assert!(matches!(check(1, 2, Ships(vec![10, 11, 12])), Err(Error::CapacityExceeded { .. })));
+        // a full destination takes nothing
+        crate::PORT_STORAGE.with(|service| {
+            let mut port = service.borrow().get(&2).unwrap();
+            port.capacity = 1;
+            service.borrow_mut().insert(2, port);
+        });
+        assert!(matches!(check(1, 2, Count(1)), Err(Error::CapacityExceeded { .. })));
+    }
+
+    #[test]
+    fn retries_with_the_same_key_return_the_original_transfer() {
+        let caller = Principal::from_slice(&[1; 29]);
+        let mut original = request(1, 2, TransferSelection::Count(2));
+        original.idempotency_key = Some("retry-1".to_string());
+        let transfer = Transfer {
+            id: 7,
+            source_port_id: 1,
+            destination_port_id: 2,
+            selection: original.selection.clone(),
+            ship_ids: vec![10, 11],
+            requested_by: caller,
+            approved_by: Some(caller),
+            timestamp: 0,
+            status: TransferStatus::Completed,
+            idempotency_key: original.idempotency_key.clone(),
+        };
+        TRANSFER_STORAGE.with(|service| service.borrow_mut().insert(transfer.id, transfer));
+        let key = idempotency_key(caller, &original).unwrap().unwrap();
+        IDEMPOTENCY_KEYS.with(|keys| keys.borrow_mut().insert(key, 7));
+
+        assert_eq!(replayed(caller, &original).unwrap().map(|transfer| transfer.id), Some(7));
+        // keys are scoped to the caller
+        assert!(replayed(Principal::from_slice(&[2; 29]), &original).unwrap().is_none());
+        assert!(replayed(caller, &request(1, 2, TransferSelection::Count(2))).unwrap().is_none());
+
+        let mut changed = original.clone();
+        changed.selection = TransferSelection::Count(1);
+        assert!(matches!(replayed(caller, &changed), Err(Error::Conflict { .. })));
+        let mut too_long = original;
+        too_long.idempotency_key = Some("k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1));
+        assert!(matches!(replayed(caller, &too_long), Err(Error::InvalidInput { .. })));
+    }
+}
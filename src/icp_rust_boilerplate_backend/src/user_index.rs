@@ -0,0 +1,171 @@
+use crate::access::StorablePrincipal;
+use crate::{Memory, User, MEMORY_MANAGER, USER_STORAGE};
+use candid::Principal;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// Long enough for any username or email validation accepts.
+const KEY_LEN: usize = 256;
+
+type Index = StableBTreeMap<Blob<KEY_LEN>, u64, Memory>;
+
+thread_local! {
+    // normalized username -> user id
+    static USERS_BY_USERNAME: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+    ));
+
+    // normalized email -> user id
+    static USERS_BY_EMAIL: RefCell<Index> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+
+    // owner principal -> user id; a principal owns at most one user
+    static USERS_BY_OWNER: RefCell<StableBTreeMap<StorablePrincipal, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+    ));
+}
+
+// usernames and emails are unique regardless of case and surrounding spaces.
+pub(crate) fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+fn key(value: &str) -> Blob<KEY_LEN> {
+    let value = normalize(value);
+    let bytes = value.as_bytes();
+    Blob::try_from(&bytes[..bytes.len().min(KEY_LEN)]).expect("index key within bounds")
+}
+
+pub(crate) fn find_by_username(username: &str) -> Option<u64> {
+    USERS_BY_USERNAME.with(|index| index.borrow().get(&key(username)))
+}
+
+pub(crate) fn find_by_email(email: &str) -> Option<u64> {
+    USERS_BY_EMAIL.with(|index| index.borrow().get(&key(email)))
+}
+
+pub(crate) fn find_by_owner(owner: &Principal) -> Option<u64> {
+    USERS_BY_OWNER.with(|index| index.borrow().get(&StorablePrincipal(*owner)))
+}
+
+// moves a user's index entries from `previous` to `current`. called on every
+// user write and delete.
+pub(crate) fn update(previous: Option<&User>, current: Option<&User>) {
+    USERS_BY_USERNAME.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            remove_entry(&mut index, &previous.username, previous.user_id);
+        }
+        if let Some(current) = current {
+            index.insert(key(&current.username), current.user_id);
+        }
+    });
+    USERS_BY_EMAIL.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            remove_entry(&mut index, &previous.email, previous.user_id);
+        }
+        if let Some(current) = current {
+            index.insert(key(&current.email), current.user_id);
+        }
+    });
+    USERS_BY_OWNER.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(owner) = previous.and_then(|previous| previous.owner) {
+            index.remove(&StorablePrincipal(owner));
+        }
+        if let Some(current) = current {
+            if let Some(owner) = current.owner {
+                index.insert(StorablePrincipal(owner), current.user_id);
+            }
+        }
+    });
+}
+
+// only drops the entry if it still points at this user; users stored before
+// uniqueness was enforced may share a value.
+fn remove_entry(index: &mut Index, value: &str, user_id: u64) {
+    let key = key(value);
+    if index.get(&key) == Some(user_id) {
+        index.remove(&key);
+    }
+}
+
+// rebuilds the indexes from USER_STORAGE. run on every upgrade, since an
+// index can be stale even when it holds the right number of entries.
+pub(crate) fn rebuild() {
+    for index in [&USERS_BY_USERNAME, &USERS_BY_EMAIL] {
+        index.with(|index| {
+            let mut index = index.borrow_mut();
+            let stale: Vec<Blob<KEY_LEN>> = index.iter().map(|(key, _)| key).collect();
+            for key in stale {
+                index.remove(&key);
+            }
+        });
+    }
+    USERS_BY_OWNER.with(|index| {
+        let mut index = index.borrow_mut();
+        let stale: Vec<StorablePrincipal> = index.iter().map(|(key, _)| key).collect();
+        for key in stale {
+            index.remove(&key);
+        }
+    });
+    USER_STORAGE.with(|service| {
+        for (_, user) in service.borrow().iter() {
+            update(None, Some(&user));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(user_id: u64, username: &str, email: &str) -> User {
+        User {
+            user_id,
+            username: username.to_string(),
+            email: email.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lookups_ignore_case_and_surrounding_spaces() {
+        update(None, Some(&user(1, "Ada", "Ada@Example.com")));
+
+        assert_eq!(find_by_username(" aDA "), Some(1));
+        assert_eq!(find_by_email("ADA@EXAMPLE.COM"), Some(1));
+        assert_eq!(find_by_username("ada2"), None);
+    }
+
+    #[test]
+    fn renames_free_the_old_names() {
+        let before = user(1, "Ada", "ada@example.com");
+        update(None, Some(&before));
+        update(Some(&before), Some(&user(1, "Lovelace", "ada@example.org")));
+
+        assert_eq!(find_by_username("ada"), None);
+        assert_eq!(find_by_email("ada@example.com"), None);
+        assert_eq!(find_by_username("LOVELACE"), Some(1));
+        assert_eq!(find_by_email("ada@example.org"), Some(1));
+    }
+
+    #[test]
+    fn removing_a_duplicate_keeps_the_other_users_entry() {
+        // users stored before uniqueness was enforced
+        let first = user(1, "ada", "ada@example.com");
+        let second = user(2, "ADA", "ada@example.com");
+        update(None, Some(&first));
+        update(None, Some(&second));
+
+        update(Some(&first), None);
+        assert_eq!(find_by_username("ada"), Some(2));
+        update(Some(&second), None);
+        assert_eq!(find_by_email("ada@example.com"), None);
+    }
+}
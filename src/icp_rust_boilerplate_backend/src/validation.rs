@@ -0,0 +1,350 @@
+use crate::berth::BerthPayload;
+use crate::ship::ShipPayload;
+use crate::user_index;
+use crate::{Error, PortPatch, PortPayload, UserPatch, UserPayload};
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_LOCATION_LEN: usize = 100;
+const MAX_CAPACITY: u32 = 10_000;
+// keeps an encoded ship within Ship::MAX_SIZE even with 4-byte characters
+const MAX_SHIP_NAME_LEN: usize = 100;
+const MAX_FLAG_LEN: usize = 50;
+const MAX_IMO_NUMBER_LEN: usize = 16;
+// keeps an encoded berth within Berth::MAX_SIZE even with 4-byte characters
+const MAX_BERTH_NAME_LEN: usize = 64;
+const USERNAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+// RFC 5321 limits
+const MAX_EMAIL_LEN: usize = 254;
+const MAX_EMAIL_LOCAL_LEN: usize = 64;
+const MAX_DOMAIN_LABEL_LEN: usize = 63;
+
+// One rejected field of a payload.
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct FieldViolation {
+    pub field: String,
+    pub reason: String,
+}
+
+// Collects every violation of a payload so callers can fix them in one go.
+#[derive(Default)]
+pub(crate) struct Violations(Vec<FieldViolation>);
+
+impl Violations {
+    pub(crate) fn add(&mut self, field: &str, reason: impl Into<String>) {
+        self.0.push(FieldViolation {
+            field: field.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    // InvalidInput listing the violations, if there are any.
+    pub(crate) fn into_result(self, what: &str) -> Result<(), Error> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let summary: Vec<String> = self
+            .0
+            .iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.reason))
+            .collect();
+        Err(Error::InvalidInput {
+            msg: format!("invalid {}: {}", what, summary.join("; ")),
+            violations: self.0,
+        })
+    }
+}
+
+pub(crate) fn validate_port(payload: &PortPayload) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    check_port_name(&mut violations, &payload.name);
+    check_location(&mut violations, &payload.location);
+    check_capacity(&mut violations, payload.capacity);
+    violations.into_result("port")
+}
+
+// `user_id` is the user being updated, which may keep its own username and email.
+pub(crate) fn validate_user(payload: &UserPayload, user_id: Option<u64>) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    check_username(&mut violations, &payload.username, user_id);
+    check_email(&mut violations, &payload.email, user_id);
+    violations.into_result("user")
+}
+
+pub(crate) fn validate_ship(payload: &ShipPayload) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    check_text(&mut violations, "imo_number", &payload.imo_number, MAX_IMO_NUMBER_LEN);
+    if !payload.imo_number.trim().chars().all(|c| c.is_ascii_alphanumeric() || c == ' ') {
+        violations.add("imo_number", "may only contain letters, digits and spaces");
+    }
+    check_text(&mut violations, "name", &payload.name, MAX_SHIP_NAME_LEN);
+    check_text(&mut violations, "flag", &payload.flag, MAX_FLAG_LEN);
+    check_dimension(&mut violations, "loa", payload.loa);
+    check_dimension(&mut violations, "beam", payload.beam);
+    check_dimension(&mut violations, "draft", payload.draft);
+    violations.into_result("ship")
+}
+
+pub(crate) fn validate_berth(payload: &BerthPayload) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    check_text(&mut violations, "name", &payload.name, MAX_BERTH_NAME_LEN);
+    check_dimension(&mut violations, "length", payload.length);
+    check_dimension(&mut violations, "max_draft", payload.max_draft);
+    violations.into_result("berth")
+}
+
+// checks only the fields a patch sets.
+pub(crate) fn validate_port_patch(patch: &PortPatch) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    if let Some(name) = &patch.name {
+        check_port_name(&mut violations, name);
+    }
+    if let Some(location) = &patch.location {
+        check_location(&mut violations, location);
+    }
+    if let Some(capacity) = patch.capacity {
+        check_capacity(&mut violations, capacity);
+    }
+    violations.into_result("port")
+}
+
+pub(crate) fn validate_user_patch(patch: &UserPatch, user_id: u64) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    if let Some(username) = &patch.username {
+        check_username(&mut violations, username, Some(user_id));
+    }
+    if let Some(email) = &patch.email {
+        check_email(&mut violations, email, Some(user_id));
+    }
+    violations.into_result("user")
+}
+
+pub(crate) fn check_port_name(violations: &mut Violations, name: &str) {
+    check_text(violations, "name", name, MAX_NAME_LEN);
+}
+
+pub(crate) fn check_location(violations: &mut Violations, location: &str) {
+    check_text(violations, "location", location, MAX_LOCATION_LEN);
+}
+
+pub(crate) fn check_capacity(violations: &mut Violations, capacity: u32) {
+    if !(1..=MAX_CAPACITY).contains(&capacity) {
+        violations.add("capacity", format!("must be between 1 and {}", MAX_CAPACITY));
+    }
+}
+
+pub(crate) fn check_username(violations: &mut Violations, username: &str, user_id: Option<u64>) {
+    let length = username.chars().count();
+    if !USERNAME_LEN.contains(&length) {
+        violations.add(
+            "username",
+            format!(
+                "must be {} to {} characters long",
+                USERNAME_LEN.start(),
+                USERNAME_LEN.end()
+            ),
+        );
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        violations.add("username", "may only contain letters, digits, '_', '.' and '-'");
+    } else if length > 0 && !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        violations.add("username", "must start with a letter or digit");
+    }
+    if user_index::find_by_username(username).is_some_and(|owner| Some(owner) != user_id) {
+        violations.add("username", "is already taken");
+    }
+}
+
+pub(crate) fn check_email(violations: &mut Violations, email: &str, user_id: Option<u64>) {
+    if let Err(reason) = check_email_format(email) {
+        violations.add("email", reason);
+        return;
+    }
+    if user_index::find_by_email(email).is_some_and(|owner| Some(owner) != user_id) {
+        violations.add("email", "is already registered");
+    }
+}
+
+// the common subset of RFC 5322 addresses: a dot-atom local part and a domain
+// of at least two LDH labels with an alphabetic top-level label.
+fn check_email_format(email: &str) -> Result<(), &'static str> {
+    if email.len() > MAX_EMAIL_LEN {
+        return Err("is too long");
+    }
+    let (local, domain) = match email.split_once('@') {
+        Some(parts) => parts,
+        None => return Err("must contain '@'"),
+    };
+    if local.is_empty() || local.len() > MAX_EMAIL_LOCAL_LEN {
+        return Err("has an empty or too long local part");
+    }
+    let atext = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c);
+    if local.split('.').any(|atom| atom.is_empty() || !atom.chars().all(atext)) {
+        return Err("has an invalid local part");
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err("must have a domain with a top-level domain");
+    }
+    let ldh = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= MAX_DOMAIN_LABEL_LEN
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if !labels.iter().all(ldh) {
+        return Err("has an invalid domain");
+    }
+    let top = labels[labels.len() - 1];
+    if top.len() < 2 || !top.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err("has an invalid top-level domain");
+    }
+    Ok(())
+}
+
+fn check_dimension(violations: &mut Violations, field: &str, metres: f64) {
+    if !(metres.is_finite() && metres > 0.0) {
+        violations.add(field, "must be a positive number of metres");
+    }
+}
+
+fn check_text(violations: &mut Violations, field: &str, value: &str, max_len: usize) {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        violations.add(field, "must not be empty");
+    } else if trimmed.chars().count() > max_len {
+        violations.add(field, format!("must be at most {} characters", max_len));
+    }
+    if trimmed.chars().any(char::is_control) {
+        violations.add(field, "must not contain control characters");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::ShipType;
+
+    fn fields(result: Result<(), Error>) -> Vec<String> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(Error::InvalidInput { violations, .. }) => violations.into_iter().map(|v| v.field).collect(),
+            Err(_) => panic!("expected InvalidInput"),
+        }
+    }
+
+    fn user(username: &str, email: &str) -> UserPayload {
+        UserPayload {
+            username: username.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    fn ship() -> ShipPayload {
+        ShipPayload {
+            imo_number: "IMO 9321483".to_string(),
+            name: "Emma Maersk".to_string(),
+            flag: "Denmark".to_string(),
+            ship_type: ShipType::Container,
+            loa: 397.0,
+            beam: 56.0,
+            draft: 16.0,
+            gross_tonnage: 170_794,
+        }
+    }
+
+    #[test]
+    fn emails_must_be_in_the_common_subset() {
+        for email in ["a@b.io", "first.last+tag@mail.example.org", "o'neil@x-y.com"] {
+            assert_eq!(check_email_format(email), Ok(()), "{}", email);
+        }
+        let long_local = format!("{}@example.com", "a".repeat(65));
+        let long_label = format!("a@{}.com", "b".repeat(64));
+        let rejected = [
+            "plain",
+            "@example.com",
+            "a..b@example.com",
+            ".a@example.com",
+            "a b@example.com",
+            "a@localhost",
+            "a@-example.com",
+            "a@example..com",
+            "a@example.c",
+            "a@example.c0m",
+            "a@b@example.com",
+            &long_local,
+            &long_label,
+        ];
+        for email in rejected {
+            assert!(check_email_format(email).is_err(), "{}", email);
+        }
+        assert_eq!(check_email_format(&format!("{}@x.io", "a".repeat(250))), Err("is too long"));
+    }
+
+    #[test]
+    fn usernames_use_a_limited_charset() {
+        for username in ["ada", "ada_lovelace", "a.b-c", "7seas"] {
+            assert!(fields(validate_user(&user(username, "a@b.io"), None)).is_empty(), "{}", username);
+        }
+        for username in ["ab", "_ada", "ada lovelace", "ädä", "ada!", &"a".repeat(33)] {
+            assert_eq!(fields(validate_user(&user(username, "a@b.io"), None)), ["username"], "{}", username);
+        }
+    }
+
+    #[test]
+    fn capacity_is_between_one_and_ten_thousand() {
+        let port = |capacity| PortPayload {
+            name: "Rotterdam".to_string(),
+            location: "Netherlands".to_string(),
+            capacity,
+        };
+        assert!(fields(validate_port(&port(1))).is_empty());
+        assert!(fields(validate_port(&port(MAX_CAPACITY))).is_empty());
+        assert_eq!(fields(validate_port(&port(0))), ["capacity"]);
+        assert_eq!(fields(validate_port(&port(MAX_CAPACITY + 1))), ["capacity"]);
+        let patch = PortPatch {
+            capacity: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(fields(validate_port_patch(&patch)), ["capacity"]);
+    }
+
+    #[test]
+    fn ship_fields_are_checked_together() {
+        assert!(fields(validate_ship(&ship())).is_empty());
+
+        let invalid = ShipPayload {
+            imo_number: "IMO-9321483".to_string(),
+            name: " ".to_string(),
+            flag: "D".repeat(MAX_FLAG_LEN + 1),
+            loa: 0.0,
+            beam: f64::NAN,
+            draft: f64::INFINITY,
+            ..ship()
+        };
+        assert_eq!(
+            fields(validate_ship(&invalid)),
+            ["imo_number", "name", "flag", "loa", "beam", "draft"]
+        );
+    }
+
+    #[test]
+    fn taken_names_are_only_reported_for_other_users() {
+        let stored = crate::User {
+            user_id: 1,
+            username: "Ada".to_string(),
+            email: "Ada@Example.com".to_string(),
+            ..Default::default()
+        };
+        user_index::update(None, Some(&stored));
+
+        assert_eq!(
+            fields(validate_user(&user("ADA", " ada@example.COM "), None)),
+            ["username", "email"]
+        );
+        assert!(fields(validate_user(&user("ada", "ada@example.com"), Some(1))).is_empty());
+    }
+}